@@ -0,0 +1,144 @@
+use std::io::Write;
+
+use flate2::{write::ZlibEncoder, Compression};
+use futures::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::SharedMockState;
+
+pub async fn serve(state: SharedMockState, listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, address)) => {
+                info!("Gateway connection from {}", address);
+                tokio::spawn(handle_connection(state.clone(), stream));
+            }
+            Err(error) => warn!("Failed to accept a gateway connection: {}", error),
+        }
+    }
+}
+
+// The handshake callback has to return the error response of tungstenite
+#[allow(clippy::result_large_err)]
+async fn handle_connection(state: SharedMockState, stream: TcpStream) {
+    let mut compress = false;
+    let handshake = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response| {
+        compress = request
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .contains("compress=zlib-stream");
+        Ok::<Response, _>(response)
+    });
+    let websocket = match handshake.await {
+        Ok(websocket) => websocket,
+        Err(error) => {
+            warn!("Websocket handshake failed: {}", error);
+            return;
+        }
+    };
+    let (mut sink, mut stream) = websocket.split();
+    let (sender, receiver) = async_channel::unbounded::<Message>();
+
+    if compress {
+        info!("Compressing with zlib-stream");
+    }
+
+    // Everything going to the bot passes through here, so dispatches of other
+    // tasks and replies of this connection can not interleave
+    let writer = tokio::spawn(async move {
+        // One zlib context for the whole connection, like Discord does
+        let mut encoder = compress.then(|| ZlibEncoder::new(vec![], Compression::default()));
+        while let Ok(message) = receiver.recv().await {
+            let is_close = matches!(message, Message::Close(_));
+            let message = match (&mut encoder, message) {
+                (Some(encoder), Message::Text(text)) => Message::Binary(deflate(encoder, &text)),
+                (_, message) => message,
+            };
+            if sink.send(message).await.is_err() || is_close {
+                break;
+            }
+        }
+    });
+
+    let heartbeat_interval = state.lock().await.heartbeat_interval;
+    send(
+        &sender,
+        json!({"op": 10, "d": {"heartbeat_interval": heartbeat_interval}}),
+    );
+
+    while let Some(Ok(message)) = stream.next().await {
+        let package: Value = match message {
+            Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(package) => package,
+                Err(error) => {
+                    warn!("Bot sent invalid JSON: {}", error);
+                    continue;
+                }
+            },
+            Message::Close(frame) => {
+                info!("Bot closed the connection: {:?}", frame);
+                break;
+            }
+            _ => continue,
+        };
+        debug!("Received op {}", package["op"]);
+
+        match package["op"].as_u64() {
+            // Heartbeat
+            Some(1) => send(&sender, json!({"op": 11})),
+            // Identify
+            Some(2) => {
+                let mut state = state.lock().await;
+                state.session_id = format!("mock-session-{}", state.next_id());
+                info!("Bot identified, session {}", state.session_id);
+                state.sessions.push(sender.clone());
+                let ready = json!({
+                    "v": 9,
+                    "user": state.user,
+                    "session_id": state.session_id,
+                    "resume_gateway_url": state.resume_gateway_url,
+                    "guilds": [],
+                });
+                state.dispatch("READY", ready);
+            }
+            // Resume
+            Some(6) => {
+                let mut state = state.lock().await;
+                let session_id = package["d"]["session_id"].as_str().unwrap_or_default();
+                let seq = package["d"]["seq"].as_u64().unwrap_or_default();
+                if session_id != state.session_id {
+                    info!("Bot tried to resume unknown session {}", session_id);
+                    send(&sender, json!({"op": 9, "d": false}));
+                    continue;
+                }
+                info!("Bot resumed session {} from {}", session_id, seq);
+                for (_, event) in state.events.iter().filter(|(sequence, _)| *sequence > seq) {
+                    sender.try_send(Message::text(event)).ok();
+                }
+                state.sessions.push(sender.clone());
+                state.dispatch("RESUMED", json!({}));
+            }
+            other => debug!("Ignoring op {:?}", other),
+        }
+    }
+
+    sender.close();
+    writer.await.ok();
+    info!("Gateway connection closed");
+}
+
+/// Compresses a package and ends it with a Z_SYNC_FLUSH, so the bot can inflate it right away
+fn deflate(encoder: &mut ZlibEncoder<Vec<u8>>, text: &str) -> Vec<u8> {
+    encoder.write_all(text.as_bytes()).unwrap();
+    encoder.flush().unwrap();
+    std::mem::take(encoder.get_mut())
+}
+
+fn send(sender: &async_channel::Sender<Message>, package: Value) {
+    sender.try_send(Message::text(package.to_string())).ok();
+}
@@ -0,0 +1,262 @@
+//! Local stand-in for the Discord Gateway and REST API
+//!
+//! Speaks enough of both to run pepe-bot end to end without a network connection.
+//! Everything the bot sends is recorded and can be inspected (and events can be injected)
+//! through the `/mock/*` routes of the REST server:
+//!
+//! - `GET /mock/requests` lists every REST request the bot made
+//! - `DELETE /mock/requests` clears that list
+//! - `GET /mock/sessions` counts the gateway connections that identified or resumed
+//! - `POST /mock/dispatch` sends `{"t": "EVENT_NAME", "d": {...}}` to the bot as a Dispatch
+//! - `POST /mock/close` closes the gateway connection with `{"code": 4000}`
+//! - `POST /mock/games/{name}` starts a mini-game in `{"channel_id": "..."}`
+//! - `GET /mock/results` lists the outcome of every game played with the simulated Dank Memer
+//! - `POST /mock/rate-limit` limits every route to `{"limit": 2, "reset_after": 1.5, "global": false}`,
+//!   a limit of 0 turns it off again
+//! - `POST /mock/fail` makes a path answer `{"path": "/channels/1/messages", "status": 401, "code": 40001}`,
+//!   `DELETE /mock/fail` lets every path succeed again
+
+mod gateway;
+mod pepe;
+mod rest;
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use clap_verbosity_flag::InfoLevel;
+use futures::lock::Mutex;
+use log::info;
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Mock Discord server for testing pepe-bot offline
+#[derive(Parser, Debug)]
+#[clap(version, about, long_about = None)]
+struct Args {
+    #[clap(flatten)]
+    verbose: clap_verbosity_flag::Verbosity<InfoLevel>,
+
+    /// Port of the gateway websocket (0 picks a free port)
+    #[clap(long, default_value_t = 0)]
+    gateway_port: u16,
+
+    /// Port of the REST API (0 picks a free port)
+    #[clap(long, default_value_t = 0)]
+    api_port: u16,
+
+    /// Heartbeat interval sent in the Hello
+    #[clap(long, default_value_t = 41250)]
+    heartbeat_interval: u64,
+
+    /// Id of the user the bot logs in as
+    #[clap(long, default_value = "900000000000000010")]
+    user_id: String,
+
+    /// Name of the user the bot logs in as
+    #[clap(long, default_value = "pepe")]
+    username: String,
+
+    /// Guild all mocked channels belong to
+    #[clap(long, default_value = "900000000000000001")]
+    guild_id: String,
+}
+
+/// Timestamp of every mocked message
+pub const TIMESTAMP: &str = "2022-03-01T12:00:00.000000+00:00";
+
+pub type SharedMockState = Arc<Mutex<MockState>>;
+
+pub struct MockState {
+    pub heartbeat_interval: u64,
+    pub user: Value,
+    pub guild_id: String,
+    pub session_id: String,
+    /// Sent with READY, the url of the gateway itself
+    pub resume_gateway_url: String,
+    pub sequence: u64,
+    /// Every dispatch sent so far, kept to replay them on a Resume
+    pub events: Vec<(u64, String)>,
+    /// Gateway connections that identified or resumed
+    pub sessions: Vec<async_channel::Sender<Message>>,
+    /// Latest version of every message sent through the gateway, by id
+    pub messages: HashMap<String, Value>,
+    /// Every REST request the bot made
+    pub requests: Vec<Value>,
+    pub pepe: pepe::Pepe,
+    pub rate_limit: Option<RateLimit>,
+    /// Requests made and when the window ends, for every rate limited route
+    pub buckets: HashMap<String, (u32, Instant)>,
+    /// Routes that answer with an error instead
+    pub failures: Vec<Failure>,
+    next_id: u64,
+}
+
+/// Error a route of the REST API answers with, set through `/mock/fail`
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub path: String,
+    pub status: u16,
+    /// JSON error code of Discord
+    pub code: u32,
+    pub message: String,
+}
+
+/// Rate limit every route of the REST API gets
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub limit: u32,
+    pub reset_after: Duration,
+    /// Whether a 429 applies to all routes
+    pub global: bool,
+}
+
+impl MockState {
+    /// Sends a Dispatch to all connected sessions
+    pub fn dispatch(&mut self, event: &str, data: Value) {
+        if let Some(id) = data["id"].as_str() {
+            match event {
+                "MESSAGE_CREATE" | "MESSAGE_UPDATE" => {
+                    self.messages.insert(id.to_string(), data.clone());
+                }
+                "MESSAGE_DELETE" => {
+                    self.messages.remove(id);
+                }
+                _ => {}
+            }
+        }
+        self.sequence += 1;
+        let package = json!({
+            "op": 0,
+            "s": self.sequence,
+            "t": event,
+            "d": data,
+        })
+        .to_string();
+        self.events.push((self.sequence, package.clone()));
+        self.sessions
+            .retain(|session| session.try_send(Message::text(&package)).is_ok());
+    }
+
+    pub fn find_message(&self, id: &str) -> Option<Value> {
+        self.messages.get(id).cloned()
+    }
+
+    /// Returns a new unique snowflake
+    pub fn next_id(&mut self) -> String {
+        self.next_id += 1;
+        self.next_id.to_string()
+    }
+
+    /// Author object of the logged in user
+    pub fn author(&self) -> Value {
+        json!({
+            "id": self.user["id"],
+            "username": self.user["username"],
+            "discriminator": self.user["discriminator"],
+            "public_flags": 0,
+            "avatar": null,
+            "bot": null,
+        })
+    }
+
+    /// Builds a message like Discord would send it
+    pub fn message(&mut self, channel_id: &str, author: Value, content: &str) -> Value {
+        json!({
+            "type": 0,
+            "tts": false,
+            "timestamp": TIMESTAMP,
+            "referenced_message": null,
+            "pinned": false,
+            "mentions": [],
+            "mention_roles": [],
+            "mention_everyone": false,
+            "id": self.next_id(),
+            "flags": 0,
+            "embeds": [],
+            "edited_timestamp": null,
+            "content": content,
+            "components": [],
+            "channel_id": channel_id,
+            "author": author,
+            "attachments": [],
+            "member": null,
+            "guild_id": self.guild_id,
+            "reactions": null,
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    env_logger::Builder::new()
+        .filter_level(args.verbose.log_level_filter())
+        .init();
+
+    let state: SharedMockState = Arc::new(Mutex::new(MockState {
+        heartbeat_interval: args.heartbeat_interval,
+        user: json!({
+            "id": args.user_id,
+            "username": args.username,
+            "discriminator": "0001",
+            "accent_color": null,
+            "avatar": null,
+            "banner": null,
+            "banner_color": null,
+            "bio": "",
+            "desktop": false,
+            "email": "pepe@localhost",
+            "flags": 0,
+            "mfa_enabled": false,
+            "mobile": false,
+            "nsfw_allowed": true,
+            "phone": null,
+            "premium": false,
+            "purchased_flags": 0,
+            "verified": true,
+        }),
+        guild_id: args.guild_id,
+        session_id: String::new(),
+        resume_gateway_url: String::new(),
+        sequence: 0,
+        events: vec![],
+        sessions: vec![],
+        messages: HashMap::new(),
+        requests: vec![],
+        pepe: pepe::Pepe::default(),
+        rate_limit: None,
+        buckets: HashMap::new(),
+        failures: vec![],
+        next_id: 950_000_000_000_000_000,
+    }));
+
+    let gateway_listener =
+        tokio::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], args.gateway_port)))
+            .await
+            .expect("Failed to bind the gateway port");
+    let gateway_address = gateway_listener.local_addr().unwrap();
+    state.lock().await.resume_gateway_url = format!("ws://{}", gateway_address);
+
+    let api_listener =
+        std::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], args.api_port)))
+            .expect("Failed to bind the API port");
+    let api_address = api_listener.local_addr().unwrap();
+
+    info!("Gateway: ws://{}", gateway_address);
+    info!("API: http://{}/api/v9", api_address);
+    // Printed on stdout as well so scripts can pick the urls up
+    println!("GATEWAY_URL=ws://{}", gateway_address);
+    println!("API_URL=http://{}/api/v9", api_address);
+
+    tokio::select! {
+        _ = gateway::serve(state.clone(), gateway_listener) => {}
+        result = rest::serve(state.clone(), api_listener) => result.expect("REST server failed"),
+        _ = tokio::signal::ctrl_c() => info!("Stopping"),
+    }
+}
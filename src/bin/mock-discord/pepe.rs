@@ -0,0 +1,881 @@
+//! Scripted stand-in for Dank Memer ("Pepe")
+//!
+//! Replies to the farming commands and plays the mini-games the way the real bot does,
+//! with the same embeds, components and message updates, and checks every click against
+//! the known correct answer. The outcome of each game is kept in [`Pepe::results`].
+
+use std::{collections::HashMap, time::Duration};
+
+use log::{debug, info};
+use rand::{seq::SliceRandom, Rng};
+use serde_json::{json, Value};
+
+use crate::{MockState, SharedMockState, TIMESTAMP};
+
+pub const PEPE_ID: &str = "270904126974590976";
+
+/// Time between a mini-game showing its riddle and asking for the answer
+const MINIGAME_DELAY: Duration = Duration::from_secs(2);
+
+/// User the mini-games are addressed to when they are not meant for the bot
+const OTHER_USER_ID: &str = "900000000000000099";
+
+const TRIVIA_QUESTIONS: [(&str, [&str; 4], usize); 3] = [
+    (
+        "What is the name of the Dank Memer mascot?",
+        ["Pepe", "Doge", "Wojak", "Trollface"],
+        0,
+    ),
+    ("How many bits are in a byte?", ["4", "16", "8", "32"], 2),
+    (
+        "Which planet is known as the Red Planet?",
+        ["Venus", "Jupiter", "Saturn", "Mars"],
+        3,
+    ),
+];
+
+const MEME_TYPES: [&str; 5] = ["Fresh", "Repost", "Intellectual", "Copypasta", "Kind"];
+
+const STREAM_GAMES: [&str; 4] = ["Fortnite", "Minecraft", "Among Us", "Rocket League"];
+
+const WORDS: [&str; 10] = [
+    "apple", "banana", "cherry", "grape", "lemon", "mango", "melon", "peach", "pear", "plum",
+];
+
+const EMOJIS: [&str; 10] = ["🐸", "🐶", "🐱", "🦊", "🐼", "🐨", "🐯", "🦁", "🐮", "🐷"];
+
+const COLORS: [&str; 4] = ["Red", "Green", "Blue", "Yellow"];
+
+const MINIGAMES: [&str; 7] = [
+    "word-order",
+    "emoji-match",
+    "soccer",
+    "color-match",
+    "dunk",
+    "boss",
+    "trivia-night",
+];
+
+/// A game waiting for clicks, by the message it is played on
+enum Game {
+    HighLow {
+        number: u8,
+    },
+    Hunt {
+        fireball: usize,
+    },
+    Fish {
+        fish: usize,
+    },
+    Trivia {
+        answer: usize,
+    },
+    PostMemes,
+    Stream,
+    Pet {
+        clicks: usize,
+    },
+    WordOrder {
+        words: Vec<String>,
+        clicked: Vec<String>,
+    },
+    EmojiMatch {
+        emoji: String,
+    },
+    Soccer {
+        keeper: usize,
+    },
+    ColorMatch {
+        color: String,
+    },
+    Dunk {
+        ball: usize,
+    },
+    Boss {
+        health: u32,
+    },
+    TriviaNight {
+        answer: usize,
+    },
+}
+
+#[derive(Default)]
+pub struct Pepe {
+    games: HashMap<String, Game>,
+    /// Whether the stream of the bot user is live
+    streaming: bool,
+    /// Outcome of every finished game
+    pub results: Vec<Value>,
+}
+
+/// Replies to a message the bot sent, if it is a Dank Memer command
+pub async fn respond(state: SharedMockState, message: Value) {
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let content = message["content"]
+        .as_str()
+        .unwrap_or_default()
+        .to_lowercase();
+    let channel_id = message["channel_id"].as_str().unwrap_or_default();
+    let mut state = state.lock().await;
+    let username = state.user["username"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let mut rng = rand::thread_rng();
+
+    let (content, embed, components, game) = match content.trim() {
+        "pls hl" => {
+            let number = rng.gen_range(1..=100);
+            (
+                String::new(),
+                Some(embed(
+                    &format!("{}'s high-low game", username),
+                    &format!(
+                        "I just chose a secret number between 1 and 100.\nIs the secret number *higher* or *lower* than **{}**.",
+                        number
+                    ),
+                )),
+                vec![row(vec![
+                    button("Lower", "hl-lower"),
+                    button("JACKPOT!", "hl-jackpot"),
+                    button("Higher", "hl-higher"),
+                ])],
+                Some(Game::HighLow { number }),
+            )
+        }
+        "pls hunt" => {
+            let fireball = rng.gen_range(0..2);
+            (
+                format!(
+                    "Dodge the Fireball\n<:Dragon:861390869696741396>\n{}<:FireBall:861390860833947678>",
+                    indent(fireball)
+                ),
+                None,
+                vec![direction_buttons("hunt")],
+                Some(Game::Hunt { fireball }),
+            )
+        }
+        "pls fish" => {
+            let fish = rng.gen_range(0..3);
+            (
+                format!(
+                    "Catch the fish!\n{}<:LegendaryFish:971430841046757418>\n<:net:938119258264502302>",
+                    indent(fish)
+                ),
+                None,
+                vec![direction_buttons("fish")],
+                Some(Game::Fish { fish }),
+            )
+        }
+        "pls trivia" => {
+            let (question, answers, answer) = TRIVIA_QUESTIONS.choose(&mut rng).unwrap();
+            (
+                String::new(),
+                Some(embed(
+                    &format!("{}'s trivia question", username),
+                    &format!(
+                        "**{}**\n*You have 15 seconds to answer with the buttons below.*",
+                        question
+                    ),
+                )),
+                vec![row(answers
+                    .iter()
+                    .enumerate()
+                    .map(|(index, answer)| button(answer, &format!("trivia-{}", index)))
+                    .collect())],
+                Some(Game::Trivia { answer: *answer }),
+            )
+        }
+        "pls pm" => (
+            String::new(),
+            Some(embed(
+                &format!("{}'s meme posting session", username),
+                "**What type of meme do you want to post?**",
+            )),
+            vec![row(MEME_TYPES
+                .iter()
+                .map(|meme| button(meme, &format!("pm-{}", meme.to_lowercase())))
+                .collect())],
+            Some(Game::PostMemes),
+        ),
+        "pls stream" => (
+            String::new(),
+            Some(embed(
+                &format!("{}'s Stream Manager", username),
+                if state.pepe.streaming {
+                    "You are live on **Twitch**, keep your viewers entertained!"
+                } else {
+                    "You are not streaming right now."
+                },
+            )),
+            if state.pepe.streaming {
+                live_stream_buttons()
+            } else {
+                stream_menu_buttons()
+            },
+            Some(Game::Stream),
+        ),
+        "pls pet" => (
+            String::new(),
+            Some(embed_titled(
+                &format!("{}'s pet", username),
+                "Your pet **Pepe** is hungry, bored and dirty.",
+            )),
+            vec![
+                row(vec![
+                    button("Feed", "pet-feed"),
+                    button("Pat", "pet-pat"),
+                    button("Wash", "pet-wash"),
+                ]),
+                row(vec![
+                    button("Play", "pet-play"),
+                    button("Train", "pet-train"),
+                    button("Walk", "pet-walk"),
+                    button("Rename", "pet-rename"),
+                    button("End Interaction", "pet-end"),
+                ]),
+            ],
+            Some(Game::Pet { clicks: 0 }),
+        ),
+        "pls dig" => {
+            plain("You dig in the dirt and brought back 1 **Worm** <:Worm:864261394920898600>")
+        }
+        "pls beg" => plain("**Elon Musk** donated ⏣ 420 to you"),
+        "pls dep all" => plain("**⏣ 1,337** deposited, current bank balance is **⏣ 9,001**"),
+        "pls work" => plain("**TERRIBLE work!** You were given ⏣ 1,000 for a one hour shift."),
+        "pls daily" => plain("You received **⏣ 25,000** in your wallet"),
+        "pls buy laptop" => plain("Successfully purchased **1 Laptop** and paid ⏣ 5,000"),
+        _ => return,
+    };
+
+    let author = pepe_author();
+    let mut reply = state.message(channel_id, author, &content);
+    reply["referenced_message"] = message.clone();
+    reply["message_reference"] = json!({
+        "channel_id": channel_id,
+        "guild_id": message["guild_id"],
+        "message_id": message["id"],
+    });
+    reply["embeds"] = Value::from(embed.into_iter().collect::<Vec<Value>>());
+    reply["components"] = Value::from(components);
+    if let (Some(game), Some(id)) = (game, reply["id"].as_str()) {
+        state.pepe.games.insert(id.to_string(), game);
+    }
+    debug!("Pepe replied to {}", content);
+    state.dispatch("MESSAGE_CREATE", reply);
+}
+
+/// Starts a mini-game in the channel, returns the id of its message
+///
+/// Mini-games are addressed to the bot user unless `for_me` is false.
+pub async fn start_minigame(
+    state: SharedMockState,
+    name: &str,
+    channel_id: &str,
+    for_me: bool,
+) -> Option<String> {
+    if !MINIGAMES.contains(&name) {
+        return None;
+    }
+
+    let mut guard = state.lock().await;
+    let target = if for_me {
+        guard.user["id"].as_str().unwrap_or_default().to_string()
+    } else {
+        OTHER_USER_ID.to_string()
+    };
+    let mut rng = rand::thread_rng();
+
+    // Some mini-games ask their question in an update, after the riddle was shown
+    let mut follow_up: Option<(String, Vec<Value>)> = None;
+    let (content, embeds, components, game) = match name {
+        "word-order" => {
+            let words: Vec<String> = WORDS
+                .choose_multiple(&mut rng, 5)
+                .map(|word| word.to_string())
+                .collect();
+            let mut shuffled = words.clone();
+            shuffled.shuffle(&mut rng);
+            follow_up = Some((
+                format!("<@!{}> Click the buttons in correct order!", target),
+                vec![row(shuffled
+                    .iter()
+                    .map(|word| button(word, &format!("order-{}", word)))
+                    .collect())],
+            ));
+            (
+                format!(
+                    "**Work for Pepe** - Remember words order!\n{}",
+                    words
+                        .iter()
+                        .map(|word| format!("`{}`", word))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                ),
+                vec![],
+                vec![],
+                Game::WordOrder {
+                    words,
+                    clicked: vec![],
+                },
+            )
+        }
+        "emoji-match" => {
+            let emoji = EMOJIS.choose(&mut rng).unwrap().to_string();
+            let buttons: Vec<Value> = EMOJIS
+                .iter()
+                .enumerate()
+                .map(|(index, emoji)| emoji_button(emoji, &format!("emoji-{}", index)))
+                .collect();
+            follow_up = Some((
+                format!("<@!{}> What was the emoji?", target),
+                vec![row(buttons[..5].to_vec()), row(buttons[5..].to_vec())],
+            ));
+            (
+                format!("**Emoji Match** - Look at the emoji closely!\n{}", emoji),
+                vec![],
+                vec![],
+                Game::EmojiMatch { emoji },
+            )
+        }
+        "soccer" => {
+            let keeper = rng.gen_range(0..3);
+            (
+                format!(
+                    "<@!{}> **Soccer** - Hit the ball into a goal where the goalkeeper is not there!\n🥅🥅🥅\n{}:levitate:\n       ⚽",
+                    target,
+                    indent(keeper)
+                ),
+                vec![],
+                vec![direction_buttons("soccer")],
+                Game::Soccer { keeper },
+            )
+        }
+        "color-match" => {
+            let words: Vec<&str> = WORDS.choose_multiple(&mut rng, 3).cloned().collect();
+            let mut colors = COLORS.to_vec();
+            colors.shuffle(&mut rng);
+            let answer = rng.gen_range(0..3);
+            let buttons = vec![row(COLORS
+                .iter()
+                .map(|color| button(color, &format!("color-{}", color.to_lowercase())))
+                .collect())];
+            follow_up = Some((
+                format!(
+                    "<@!{}> What color was next to the word `{}`?",
+                    target, words[answer]
+                ),
+                buttons.clone(),
+            ));
+            (
+                format!(
+                    "**Color Match** - Remember the color of each word!\n{}",
+                    words
+                        .iter()
+                        .zip(colors.iter())
+                        .map(|(word, color)| format!("<:{}:863886248202059807> `{}`", color, word))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                ),
+                vec![],
+                buttons,
+                Game::ColorMatch {
+                    color: colors[answer].to_string(),
+                },
+            )
+        }
+        "dunk" => {
+            let ball = rng.gen_range(0..3);
+            (
+                format!(
+                    "<@!{}> **Dunk the ball!**\n<:hoop:882651546393645096>\n{}🏀",
+                    target,
+                    indent(ball)
+                ),
+                vec![],
+                vec![direction_buttons("dunk")],
+                Game::Dunk { ball },
+            )
+        }
+        "boss" => (
+            "Attack the boss by clicking `Attack`! **Pepe Boss** has 5 health left".to_string(),
+            vec![],
+            vec![row(vec![button("Attack", "boss-attack")])],
+            Game::Boss { health: 5 },
+        ),
+        "trivia-night" => {
+            let answer = rng.gen_range(0..5);
+            (
+                String::new(),
+                vec![embed(
+                    "Trivia Night",
+                    "**What is the best meme?**\nYou have 15 seconds to answer",
+                )],
+                vec![row((0..5)
+                    .map(|index| {
+                        button(
+                            &format!("Answer {}", index + 1),
+                            &format!("night-{}", index),
+                        )
+                    })
+                    .collect())],
+                Game::TriviaNight { answer },
+            )
+        }
+        _ => unreachable!(),
+    };
+
+    let channel_id = channel_id.to_string();
+    let author = pepe_author();
+    let mut message = guard.message(&channel_id, author, &content);
+    message["embeds"] = Value::from(embeds);
+    message["components"] = Value::from(components);
+    let message_id = message["id"].as_str().unwrap_or_default().to_string();
+    guard.pepe.games.insert(message_id.clone(), game);
+    info!("Started {} in {}", name, channel_id);
+    guard.dispatch("MESSAGE_CREATE", message);
+    drop(guard);
+
+    if let Some((content, components)) = follow_up {
+        let message_id = message_id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(MINIGAME_DELAY).await;
+            update(&mut *state.lock().await, &message_id, |message| {
+                message["content"] = Value::from(content);
+                message["components"] = Value::from(components);
+            });
+        });
+    }
+
+    Some(message_id)
+}
+
+/// Handles a click or selection on one of Pepe's messages
+pub async fn interact(state: SharedMockState, interaction: Value) {
+    // Discord acknowledges the interaction before the bot edits its message
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let message_id = interaction["message_id"].as_str().unwrap_or_default();
+    let custom_id = interaction["data"]["custom_id"]
+        .as_str()
+        .unwrap_or_default();
+    let mut guard = state.lock().await;
+    let state = &mut *guard;
+    let game = match state.pepe.games.remove(message_id) {
+        Some(game) => game,
+        None => {
+            debug!("Interaction on unknown message {}", message_id);
+            return;
+        }
+    };
+    let message = match state.find_message(message_id) {
+        Some(message) => message,
+        None => return,
+    };
+    let clicked = find_button(&message, custom_id);
+    let mut label = clicked
+        .as_ref()
+        .and_then(|(row, column)| {
+            message["components"][*row]["components"][*column]["label"].as_str()
+        })
+        .unwrap_or_default()
+        .to_string();
+    let index = clicked
+        .map(|(row, column)| row * 5 + column)
+        .unwrap_or(usize::MAX);
+
+    // Games that take more than one click put themselves back and return early
+    let (name, expected, correct, description): (&str, String, bool, Option<String>) = match game {
+        Game::HighLow { number } => {
+            let expected = if number <= 50 { 2 } else { 0 };
+            let secret = if expected == 2 {
+                rand::thread_rng().gen_range(number.max(1)..=100)
+            } else {
+                rand::thread_rng().gen_range(1..=number)
+            };
+            (
+                "hl",
+                (if expected == 2 { "Higher" } else { "Lower" }).to_string(),
+                index == expected,
+                Some(format!(
+                    "{}\nYour hint was **{}**. The hidden number was **{}**",
+                    if index == expected {
+                        "**You won ⏣ 1,000!**"
+                    } else {
+                        "**You lost!**"
+                    },
+                    number,
+                    secret
+                )),
+            )
+        }
+        Game::Hunt { fireball } => (
+            "hunt",
+            format!("not {}", direction(fireball)),
+            index != fireball,
+            None,
+        ),
+        Game::Fish { fish } => ("fish", direction(fish).to_string(), index == fish, None),
+        Game::Soccer { keeper } => (
+            "soccer",
+            format!("not {}", direction(keeper)),
+            index != keeper,
+            None,
+        ),
+        Game::Dunk { ball } => ("dunk", direction(ball).to_string(), index == ball, None),
+        Game::Trivia { answer } => (
+            "trivia",
+            answer_label(&message, answer),
+            index == answer,
+            None,
+        ),
+        Game::TriviaNight { answer } => (
+            "trivia-night",
+            answer_label(&message, answer),
+            index == answer,
+            None,
+        ),
+        Game::PostMemes => {
+            let broken = rand::thread_rng().gen_range(0..4) == 0;
+            (
+                "pm",
+                "any".to_string(),
+                clicked.is_some(),
+                Some(if broken {
+                    "Your posting session went horribly wrong, your **Laptop** is broken"
+                        .to_string()
+                } else {
+                    format!("You posted a **{}** meme and got ⏣ 420 from ads", label)
+                }),
+            )
+        }
+        Game::EmojiMatch { emoji } => {
+            let clicked_emoji = clicked
+                .map(|(row, column)| {
+                    message["components"][row]["components"][column]["emoji"]["name"].clone()
+                })
+                .unwrap_or(Value::Null);
+            let correct = clicked_emoji.as_str() == Some(&emoji);
+            label = clicked_emoji.as_str().unwrap_or_default().to_string();
+            ("emoji-match", emoji, correct, None)
+        }
+        Game::ColorMatch { color } => {
+            let correct = label == color;
+            ("color-match", color, correct, None)
+        }
+        Game::WordOrder { words, mut clicked } => {
+            clicked.push(label.clone());
+            if clicked.len() < words.len() {
+                let done = clicked.len();
+                update(state, message_id, |message| {
+                    disable_button(message, custom_id);
+                });
+                state
+                    .pepe
+                    .games
+                    .insert(message_id.to_string(), Game::WordOrder { words, clicked });
+                debug!("Word order: {} clicked", done);
+                return;
+            }
+            let correct = clicked == words;
+            label = clicked.join(" ");
+            ("word-order", words.join(" "), correct, None)
+        }
+        Game::Boss { health } => {
+            let health = health.saturating_sub(1);
+            if health > 0 {
+                update(state, message_id, |message| {
+                    message["content"] = Value::from(format!(
+                        "Attack the boss by clicking `Attack`! **Pepe Boss** has {} health left",
+                        health
+                    ));
+                });
+                state
+                    .pepe
+                    .games
+                    .insert(message_id.to_string(), Game::Boss { health });
+                return;
+            }
+            (
+                "boss",
+                "defeated".to_string(),
+                true,
+                Some("**Pepe Boss** has been defeated!".to_string()),
+            )
+        }
+        Game::Pet { clicks } => {
+            if custom_id != "pet-end" {
+                update(state, message_id, |message| {
+                    disable_button(message, custom_id);
+                });
+                state
+                    .pepe
+                    .games
+                    .insert(message_id.to_string(), Game::Pet { clicks: clicks + 1 });
+                return;
+            }
+            (
+                "pet",
+                "3 actions".to_string(),
+                clicks >= 3,
+                Some("Your pet is happy!".to_string()),
+            )
+        }
+        Game::Stream => {
+            let streaming = state.pepe.streaming;
+            let components = match custom_id {
+                "stream-live" if !streaming => Some(vec![
+                    row(vec![select_menu(
+                        "stream-game",
+                        "Select a game...",
+                        &STREAM_GAMES,
+                    )]),
+                    row(vec![
+                        disabled(button("Go Live", "stream-start")),
+                        button("Back", "stream-back"),
+                    ]),
+                ]),
+                "stream-game" => {
+                    let game = interaction["data"]["values"][0]
+                        .as_str()
+                        .unwrap_or_default();
+                    Some(vec![
+                        row(vec![select_menu("stream-game", game, &STREAM_GAMES)]),
+                        row(vec![
+                            button("Go Live", "stream-start"),
+                            button("Back", "stream-back"),
+                        ]),
+                    ])
+                }
+                "stream-start" => {
+                    state.pepe.streaming = true;
+                    Some(live_stream_buttons())
+                }
+                "stream-ad" | "stream-chat" | "stream-donations" if streaming => {
+                    let mut components = live_stream_buttons();
+                    for button in components[0]["components"].as_array_mut().unwrap() {
+                        if button["custom_id"] == custom_id {
+                            button["disabled"] = Value::from(true);
+                        }
+                    }
+                    Some(components)
+                }
+                "stream-back" => Some(stream_menu_buttons()),
+                "stream-stop" => {
+                    state.pepe.streaming = false;
+                    None
+                }
+                _ => None,
+            };
+            match components {
+                Some(components) => {
+                    update(state, message_id, |message| {
+                        message["components"] = Value::from(components);
+                    });
+                    state
+                        .pepe
+                        .games
+                        .insert(message_id.to_string(), Game::Stream);
+                    return;
+                }
+                None => ("stream", "live".to_string(), state.pepe.streaming, None),
+            }
+        }
+    };
+
+    info!(
+        "{}: clicked {:?}, expected {} ({})",
+        name,
+        label,
+        expected,
+        if correct { "correct" } else { "wrong" }
+    );
+    state.pepe.results.push(json!({
+        "game": name,
+        "message_id": message_id,
+        "clicked": label,
+        "expected": expected,
+        "correct": correct,
+    }));
+
+    update(state, message_id, |message| {
+        disable_all(message);
+        if let Some(description) = description {
+            if message["embeds"][0].is_object() {
+                message["embeds"][0]["description"] = Value::from(description);
+            } else {
+                message["content"] = Value::from(description);
+            }
+        }
+        if let Some((row, column)) = clicked {
+            message["components"][row]["components"][column]["style"] =
+                Value::from(if correct { 3 } else { 4 });
+        }
+    });
+}
+
+/// Sends the edited version of a message as MESSAGE_UPDATE
+fn update(state: &mut MockState, message_id: &str, edit: impl FnOnce(&mut Value)) {
+    if let Some(mut message) = state.find_message(message_id) {
+        edit(&mut message);
+        message["edited_timestamp"] = Value::from(TIMESTAMP);
+        state.dispatch("MESSAGE_UPDATE", message);
+    }
+}
+
+fn pepe_author() -> Value {
+    json!({
+        "id": PEPE_ID,
+        "username": "Dank Memer",
+        "discriminator": "5192",
+        "public_flags": 65536,
+        "bot": true,
+        "avatar": null,
+    })
+}
+
+fn plain(content: &str) -> (String, Option<Value>, Vec<Value>, Option<Game>) {
+    (content.to_string(), None, vec![], None)
+}
+
+fn embed(author: &str, description: &str) -> Value {
+    json!({
+        "type": "rich",
+        "author": {"name": author},
+        "description": description,
+        "color": 3_092_790,
+    })
+}
+
+fn embed_titled(title: &str, description: &str) -> Value {
+    json!({
+        "type": "rich",
+        "title": title,
+        "description": description,
+        "color": 3_092_790,
+    })
+}
+
+fn row(components: Vec<Value>) -> Value {
+    json!({"type": 1, "components": components})
+}
+
+fn button(label: &str, custom_id: &str) -> Value {
+    json!({
+        "type": 2,
+        "style": 2,
+        "label": label,
+        "custom_id": custom_id,
+        "disabled": false,
+    })
+}
+
+fn emoji_button(emoji: &str, custom_id: &str) -> Value {
+    json!({
+        "type": 2,
+        "style": 2,
+        "custom_id": custom_id,
+        "emoji": {"name": emoji, "id": "0"},
+        "disabled": false,
+    })
+}
+
+fn select_menu(custom_id: &str, placeholder: &str, options: &[&str]) -> Value {
+    json!({
+        "type": 3,
+        "custom_id": custom_id,
+        "placeholder": placeholder,
+        "min_values": 1,
+        "max_values": 1,
+        "options": options
+            .iter()
+            .map(|option| json!({"label": option, "value": option.to_lowercase()}))
+            .collect::<Vec<Value>>(),
+        "disabled": false,
+    })
+}
+
+fn disabled(mut component: Value) -> Value {
+    component["disabled"] = Value::from(true);
+    component
+}
+
+fn direction_buttons(prefix: &str) -> Value {
+    row(vec![
+        button("Left", &format!("{}-left", prefix)),
+        button("Middle", &format!("{}-middle", prefix)),
+        button("Right", &format!("{}-right", prefix)),
+    ])
+}
+
+fn stream_menu_buttons() -> Vec<Value> {
+    vec![row(vec![
+        button("Go Live", "stream-live"),
+        disabled(button("Read Chat", "stream-chat")),
+        button("End Interaction", "stream-end"),
+    ])]
+}
+
+fn live_stream_buttons() -> Vec<Value> {
+    vec![
+        row(vec![
+            button("Run AD", "stream-ad"),
+            button("Read Chat", "stream-chat"),
+            button("Collect Donations", "stream-donations"),
+        ]),
+        row(vec![
+            button("Stop Stream", "stream-stop"),
+            button("End Interaction", "stream-end"),
+        ]),
+    ]
+}
+
+/// Indentation Dank Memer uses to place an emoji in one of three columns
+fn indent(column: usize) -> String {
+    " ".repeat(column * 7)
+}
+
+fn answer_label(message: &Value, answer: usize) -> String {
+    message["components"][0]["components"][answer]["label"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn direction(column: usize) -> &'static str {
+    ["Left", "Middle", "Right"][column]
+}
+
+/// Row and column of the component with the custom id
+fn find_button(message: &Value, custom_id: &str) -> Option<(usize, usize)> {
+    message["components"]
+        .as_array()?
+        .iter()
+        .enumerate()
+        .find_map(|(row_index, row)| {
+            row["components"]
+                .as_array()?
+                .iter()
+                .position(|component| component["custom_id"] == custom_id)
+                .map(|column| (row_index, column))
+        })
+}
+
+fn disable_button(message: &mut Value, custom_id: &str) {
+    if let Some((row, column)) = find_button(message, custom_id) {
+        message["components"][row]["components"][column]["disabled"] = Value::from(true);
+    }
+}
+
+fn disable_all(message: &mut Value) {
+    if let Some(rows) = message["components"].as_array_mut() {
+        for row in rows {
+            if let Some(components) = row["components"].as_array_mut() {
+                for component in components {
+                    component["disabled"] = Value::from(true);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,490 @@
+use std::{
+    convert::Infallible,
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    body::to_bytes,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::info;
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
+
+use crate::{pepe, Failure, MockState, RateLimit, SharedMockState, TIMESTAMP};
+
+const API_PREFIX: &str = "/api/v9";
+
+pub async fn serve(state: SharedMockState, listener: std::net::TcpListener) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(state.clone(), request)
+            }))
+        }
+    });
+    Server::from_tcp(listener)?.serve(make_service).await
+}
+
+async fn handle_request(
+    state: SharedMockState,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let query = request.uri().query().unwrap_or_default().to_string();
+    let body = to_bytes(request.into_body()).await.unwrap_or_default();
+    let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let response = match (&method, segments.as_slice()) {
+        (&Method::GET, ["mock", "requests"]) => json_response(
+            StatusCode::OK,
+            Value::from(state.lock().await.requests.clone()),
+        ),
+        (&Method::DELETE, ["mock", "requests"]) => {
+            state.lock().await.requests.clear();
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        (&Method::GET, ["mock", "sessions"]) => json_response(
+            StatusCode::OK,
+            json!({ "sessions": state.lock().await.sessions.len() }),
+        ),
+        (&Method::POST, ["mock", "dispatch"]) => match body["t"].as_str() {
+            Some(event) => {
+                state.lock().await.dispatch(event, body["d"].clone());
+                empty_response(StatusCode::NO_CONTENT)
+            }
+            None => error_response(StatusCode::BAD_REQUEST, "Missing event name \"t\""),
+        },
+        (&Method::POST, ["mock", "games", name]) => {
+            let channel_id = body["channel_id"].as_str().unwrap_or("900000000000000002");
+            let for_me = body["for_me"].as_bool().unwrap_or(true);
+            match pepe::start_minigame(state, name, channel_id, for_me).await {
+                Some(message_id) => {
+                    json_response(StatusCode::OK, json!({ "message_id": message_id }))
+                }
+                None => error_response(StatusCode::NOT_FOUND, "Unknown mini-game"),
+            }
+        }
+        (&Method::GET, ["mock", "results"]) => json_response(
+            StatusCode::OK,
+            Value::from(state.lock().await.pepe.results.clone()),
+        ),
+        (&Method::POST, ["mock", "rate-limit"]) => {
+            let limit = body["limit"].as_u64().unwrap_or_default() as u32;
+            let mut state = state.lock().await;
+            state.buckets.clear();
+            state.rate_limit = (limit > 0).then(|| RateLimit {
+                limit,
+                reset_after: Duration::from_secs_f64(body["reset_after"].as_f64().unwrap_or(1.0)),
+                global: body["global"].as_bool().unwrap_or(false),
+            });
+            info!("Rate limit: {:?}", state.rate_limit);
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, ["mock", "fail"]) => match body["path"].as_str() {
+            Some(path) => {
+                let failure = Failure {
+                    path: path.to_string(),
+                    status: body["status"].as_u64().unwrap_or(500) as u16,
+                    code: body["code"].as_u64().unwrap_or_default() as u32,
+                    message: body["message"]
+                        .as_str()
+                        .unwrap_or("Failed by mock")
+                        .to_string(),
+                };
+                info!("Failing {:?}", failure);
+                state.lock().await.failures.push(failure);
+                empty_response(StatusCode::NO_CONTENT)
+            }
+            None => error_response(StatusCode::BAD_REQUEST, "Missing \"path\""),
+        },
+        (&Method::DELETE, ["mock", "fail"]) => {
+            state.lock().await.failures.clear();
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, ["mock", "close"]) => {
+            let code = body["code"].as_u64().unwrap_or(1000) as u16;
+            let mut state = state.lock().await;
+            for session in state.sessions.drain(..) {
+                session
+                    .try_send(Message::Close(Some(CloseFrame {
+                        code: CloseCode::from(code),
+                        reason: "Closed by mock".into(),
+                    })))
+                    .ok();
+            }
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        _ if path.starts_with(API_PREFIX) => {
+            let route = path[API_PREFIX.len()..].to_string();
+            info!("{} {}", method, route);
+            let failure = {
+                let state = state.lock().await;
+                state
+                    .failures
+                    .iter()
+                    .find(|failure| failure.path == route)
+                    .cloned()
+            };
+            let rate_limit = {
+                let mut state = state.lock().await;
+                let rate_limit = count_request(&mut state, &format!("{} {}", method, route));
+                let limited = rate_limit.as_ref().map(|rate_limit| rate_limit.limited);
+                state.requests.push(json!({
+                    "method": method.as_str(),
+                    "path": route,
+                    "body": body,
+                    "rate_limited": limited.unwrap_or(false),
+                }));
+                rate_limit
+            };
+            match (failure, rate_limit) {
+                (Some(failure), _) => {
+                    info!("Failing {} {} with {}", method, route, failure.status);
+                    discord_error(
+                        StatusCode::from_u16(failure.status)
+                            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                        failure.code,
+                        &failure.message,
+                    )
+                }
+                (None, Some(rate_limit)) if rate_limit.limited => {
+                    info!("Rate limited {} {}", method, route);
+                    let mut response = json_response(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        json!({
+                            "message": "You are being rate limited.",
+                            "retry_after": rate_limit.reset_after.as_secs_f64(),
+                            "global": rate_limit.global,
+                        }),
+                    );
+                    rate_limit.add_headers(&mut response);
+                    response
+                }
+                (None, Some(rate_limit)) => {
+                    let mut response =
+                        handle_api_request(state, &method, &route, &query, body).await;
+                    rate_limit.add_headers(&mut response);
+                    response
+                }
+                (None, None) => handle_api_request(state, &method, &route, &query, body).await,
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "404: Not Found"),
+    };
+    Ok(response)
+}
+
+async fn handle_api_request(
+    shared_state: SharedMockState,
+    method: &Method,
+    route: &str,
+    query: &str,
+    body: Value,
+) -> Response<Body> {
+    let segments: Vec<&str> = route.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        (&Method::POST, ["channels", channel_id, "messages"]) => {
+            let mut state = shared_state.lock().await;
+            let author = state.author();
+            let content = body["content"].as_str().unwrap_or_default();
+            if content.is_empty() {
+                return discord_error(
+                    StatusCode::BAD_REQUEST,
+                    50006,
+                    "Cannot send an empty message",
+                );
+            }
+            if content.chars().count() > 2000 {
+                return json_response(
+                    StatusCode::BAD_REQUEST,
+                    json!({
+                        "code": 50035,
+                        "message": "Invalid Form Body",
+                        "errors": {"content": {"_errors": [{
+                            "code": "BASE_TYPE_MAX_LENGTH",
+                            "message": "Must be 2000 or fewer in length."
+                        }]}}
+                    }),
+                );
+            }
+            let mut message = state.message(channel_id, author, content);
+            if let Some(message_id) = body["message_reference"]["message_id"].as_str() {
+                message["message_reference"] = body["message_reference"].clone();
+                message["referenced_message"] =
+                    state.find_message(message_id).unwrap_or(Value::Null);
+            }
+            state.dispatch("MESSAGE_CREATE", message.clone());
+            tokio::spawn(pepe::respond(shared_state.clone(), message.clone()));
+            json_response(StatusCode::OK, message)
+        }
+        (&Method::POST, ["users", "@me", "channels"]) => {
+            let mut state = shared_state.lock().await;
+            let recipient_id = body["recipient_id"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            let channel_id = state.next_id();
+            json_response(
+                StatusCode::OK,
+                json!({
+                    "id": channel_id,
+                    "type": 1,
+                    "recipients": [{"id": recipient_id}],
+                }),
+            )
+        }
+        (&Method::GET, ["channels", channel_id, "messages"]) => {
+            let state = shared_state.lock().await;
+            let parameter = |name: &str| {
+                query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+                    .and_then(|value| value.parse::<u64>().ok())
+            };
+            let before = parameter("before").unwrap_or(u64::MAX);
+            let limit = parameter("limit").unwrap_or(50).min(100) as usize;
+            let mut messages: Vec<(u64, Value)> = state
+                .messages
+                .values()
+                .filter(|message| message["channel_id"] == *channel_id)
+                .filter_map(|message| {
+                    Some((message["id"].as_str()?.parse().ok()?, message.clone()))
+                })
+                .filter(|(id, _)| *id < before)
+                .collect();
+            // Newest first, like Discord
+            messages.sort_by(|(a, _), (b, _)| b.cmp(a));
+            let messages: Vec<Value> = messages
+                .into_iter()
+                .take(limit)
+                .map(|(_, message)| message)
+                .collect();
+            json_response(StatusCode::OK, Value::from(messages))
+        }
+        (&Method::PATCH, ["channels", _, "messages", message_id]) => {
+            let mut state = shared_state.lock().await;
+            let mut message = match state.find_message(message_id) {
+                Some(message) => message,
+                None => return discord_error(StatusCode::NOT_FOUND, 10008, "Unknown Message"),
+            };
+            if message["author"]["id"] != state.user["id"] {
+                return discord_error(
+                    StatusCode::FORBIDDEN,
+                    50005,
+                    "Cannot edit a message authored by another user",
+                );
+            }
+            if let Some(content) = body["content"].as_str() {
+                message["content"] = Value::from(content);
+            }
+            message["edited_timestamp"] = Value::from(TIMESTAMP);
+            state.dispatch("MESSAGE_UPDATE", message.clone());
+            json_response(StatusCode::OK, message)
+        }
+        (&Method::DELETE, ["channels", channel_id, "messages", message_id]) => {
+            let mut state = shared_state.lock().await;
+            let message = match state.find_message(message_id) {
+                Some(message) => message,
+                None => return discord_error(StatusCode::NOT_FOUND, 10008, "Unknown Message"),
+            };
+            // The bot user has no permissions in the mock guild
+            if message["author"]["id"] != state.user["id"] {
+                return discord_error(StatusCode::FORBIDDEN, 50013, "Missing Permissions");
+            }
+            let guild_id = state.guild_id.clone();
+            state.dispatch(
+                "MESSAGE_DELETE",
+                json!({"id": message_id, "channel_id": channel_id, "guild_id": guild_id}),
+            );
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        (
+            &Method::PUT | &Method::DELETE,
+            ["channels", channel_id, "messages", message_id, "reactions", emoji, "@me"],
+        ) => {
+            let mut state = shared_state.lock().await;
+            let mut message = match state.find_message(message_id) {
+                Some(message) => message,
+                None => return discord_error(StatusCode::NOT_FOUND, 10008, "Unknown Message"),
+            };
+            let emoji = percent_decode(emoji);
+            let emoji = match emoji.split_once(':') {
+                Some((name, id)) => json!({"id": id, "name": name}),
+                None => json!({"id": null, "name": emoji}),
+            };
+            let added = method == Method::PUT;
+            if !update_own_reaction(&mut message, &emoji, added) {
+                // Discord does not mind reacting twice or removing what is not there
+                return empty_response(StatusCode::NO_CONTENT);
+            }
+            // Reactions do not update the message, they only have events of their own
+            state.messages.insert(message_id.to_string(), message);
+            let event = json!({
+                "user_id": state.user["id"],
+                "channel_id": channel_id,
+                "message_id": message_id,
+                "guild_id": state.guild_id,
+                "emoji": emoji,
+            });
+            let name = if added {
+                "MESSAGE_REACTION_ADD"
+            } else {
+                "MESSAGE_REACTION_REMOVE"
+            };
+            state.dispatch(name, event);
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, ["interactions"]) => {
+            tokio::spawn(pepe::interact(shared_state.clone(), body));
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "404: Not Found"),
+    }
+}
+
+/// Adds or removes the reaction of the bot user in the reactions of a message, false when it
+/// already was that way
+fn update_own_reaction(message: &mut Value, emoji: &Value, added: bool) -> bool {
+    if !message["reactions"].is_array() {
+        message["reactions"] = json!([]);
+    }
+    let reactions = message["reactions"].as_array_mut().unwrap();
+    let index = reactions
+        .iter()
+        .position(|reaction| reaction["emoji"] == *emoji);
+    match (index, added) {
+        (Some(index), true) if reactions[index]["me"] == true => false,
+        (Some(index), true) => {
+            let count = reactions[index]["count"].as_u64().unwrap_or_default();
+            reactions[index]["count"] = Value::from(count + 1);
+            reactions[index]["me"] = Value::from(true);
+            true
+        }
+        (None, true) => {
+            reactions.push(json!({"count": 1, "me": true, "emoji": emoji}));
+            true
+        }
+        (Some(index), false) if reactions[index]["me"] == true => {
+            let count = reactions[index]["count"].as_u64().unwrap_or_default();
+            if count <= 1 {
+                reactions.remove(index);
+            } else {
+                reactions[index]["count"] = Value::from(count - 1);
+                reactions[index]["me"] = Value::from(false);
+            }
+            true
+        }
+        (_, false) => false,
+    }
+}
+
+fn percent_decode(segment: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Rate limit of the bucket a request went to
+struct BucketState {
+    bucket: String,
+    limit: u32,
+    remaining: u32,
+    reset_after: Duration,
+    global: bool,
+    limited: bool,
+}
+
+impl BucketState {
+    fn add_headers(&self, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        let reset_after = format!("{:.3}", self.reset_after.as_secs_f64());
+        headers.insert("X-RateLimit-Bucket", self.bucket.parse().unwrap());
+        headers.insert("X-RateLimit-Limit", self.limit.into());
+        headers.insert("X-RateLimit-Remaining", self.remaining.into());
+        headers.insert("X-RateLimit-Reset-After", reset_after.parse().unwrap());
+        if self.limited {
+            headers.insert("Retry-After", reset_after.parse().unwrap());
+            if self.global {
+                headers.insert("X-RateLimit-Global", "true".parse().unwrap());
+            }
+        }
+    }
+}
+
+/// Counts the request against its bucket, nothing is counted without a rate limit
+fn count_request(state: &mut MockState, route: &str) -> Option<BucketState> {
+    let rate_limit = state.rate_limit?;
+    let now = Instant::now();
+    let key = if rate_limit.global { "global" } else { route };
+    let (used, reset) = state
+        .buckets
+        .entry(key.to_string())
+        .or_insert((0, now + rate_limit.reset_after));
+    if *reset <= now {
+        *used = 0;
+        *reset = now + rate_limit.reset_after;
+    }
+    *used += 1;
+    Some(BucketState {
+        // Discord hands out opaque hashes, the route works just as well here
+        bucket: format!("{:x}", hash(key)),
+        limit: rate_limit.limit,
+        remaining: rate_limit.limit.saturating_sub(*used),
+        reset_after: reset.saturating_duration_since(now),
+        global: rate_limit.global,
+        limited: *used > rate_limit.limit,
+    })
+}
+
+fn hash(value: &str) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Error in the format of the Discord API
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    discord_error(status, 0, message)
+}
+
+/// Error with one of the JSON error codes of Discord
+fn discord_error(status: StatusCode, code: u32, message: &str) -> Response<Body> {
+    json_response(status, json!({"code": code, "message": message}))
+}
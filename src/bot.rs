@@ -0,0 +1,176 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use crate::{
+    custom_error::BotError,
+    discord_client::{
+        connect, ClientConfig, DEFAULT_API_URL, DEFAULT_GATEWAY_URL, DEFAULT_MAX_RECONNECT_ATTEMPTS,
+    },
+    discord_commands::{Command, CommandRegistry, EventHandler},
+    replay::replay,
+};
+
+/// A configured bot, ready to connect to Discord or to replay a capture
+pub struct Bot {
+    config: ClientConfig,
+    registry: CommandRegistry,
+    shutdown: Arc<watch::Sender<bool>>,
+    /// Made with the sender, so a shutdown before `connect` or `replay` is not lost
+    shutdown_receiver: watch::Receiver<bool>,
+}
+
+/// Stops a running bot, taken from `Bot::shutdown_handle` before connecting or replaying
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    /// Stops farming, gives running handlers a moment to finish and makes `connect` return
+    pub fn shutdown(&self) {
+        self.sender.send_replace(true);
+    }
+}
+
+impl Bot {
+    /// Starts building a bot for the account of the token
+    pub fn builder(token: &str) -> BotBuilder {
+        BotBuilder::new(token)
+    }
+
+    /// Handle to stop the bot, also works when used before connecting
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            sender: self.shutdown.clone(),
+        }
+    }
+
+    /// Keeps the bot connected to the Discord Gateway until its `ShutdownHandle` is used,
+    /// see `discord_client::connect`
+    pub async fn connect(self) -> Result<(), BotError> {
+        // Kept until connect returns, so the receiver does not see the sender go away
+        let _sender = self.shutdown;
+        connect(self.config, self.registry, self.shutdown_receiver).await
+    }
+
+    /// Runs the handlers against a capture made with `record` until it ends or the
+    /// `ShutdownHandle` is used, see `replay::replay`
+    pub async fn replay(self, capture: &Path) -> Result<(), BotError> {
+        let _sender = self.shutdown;
+        replay(self.config, self.registry, capture, self.shutdown_receiver).await
+    }
+}
+
+/// Builds a `Bot`, everything but the token is optional
+///
+/// The built-in Dank Memer commands are registered unless `without_default_commands` is called,
+/// commands and event handlers added with the builder run after them.
+pub struct BotBuilder {
+    config: ClientConfig,
+    registry: CommandRegistry,
+}
+
+impl BotBuilder {
+    pub fn new(token: &str) -> BotBuilder {
+        BotBuilder {
+            config: ClientConfig {
+                token: token.to_string(),
+                master_id: None,
+                channel_id: None,
+                max_reconnect_attempts: DEFAULT_MAX_RECONNECT_ATTEMPTS,
+                state_file: None,
+                gateway_url: DEFAULT_GATEWAY_URL.to_string(),
+                api_url: DEFAULT_API_URL.to_string(),
+                record: None,
+                dry_run: false,
+                compress: false,
+            },
+            registry: CommandRegistry::default(),
+        }
+    }
+
+    /// The master of this bot (can control the bot)
+    pub fn master_id(mut self, master_id: Option<String>) -> BotBuilder {
+        self.config.master_id = master_id;
+        self
+    }
+
+    /// The default channel in which the bot runs
+    pub fn channel_id(mut self, channel_id: Option<String>) -> BotBuilder {
+        self.config.channel_id = channel_id;
+        self
+    }
+
+    /// How many times in a row to try reconnecting before giving up, 0 never gives up
+    pub fn max_reconnect_attempts(mut self, max_reconnect_attempts: u32) -> BotBuilder {
+        self.config.max_reconnect_attempts = max_reconnect_attempts;
+        self
+    }
+
+    /// File to keep the farming channel and command cooldowns in between runs
+    pub fn state_file(mut self, state_file: Option<PathBuf>) -> BotBuilder {
+        self.config.state_file = state_file;
+        self
+    }
+
+    /// Base url of the Discord Gateway
+    pub fn gateway_url(mut self, gateway_url: &str) -> BotBuilder {
+        self.config.gateway_url = gateway_url.to_string();
+        self
+    }
+
+    /// Base url of the Discord REST API
+    pub fn api_url(mut self, api_url: &str) -> BotBuilder {
+        self.config.api_url = api_url.to_string();
+        self
+    }
+
+    /// JSONL file to record the gateway and REST traffic to
+    pub fn record(mut self, record: Option<PathBuf>) -> BotBuilder {
+        self.config.record = record;
+        self
+    }
+
+    /// Log the requests to the REST API instead of sending them
+    pub fn dry_run(mut self, dry_run: bool) -> BotBuilder {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    /// Ask the gateway for zlib-stream transport compression
+    pub fn compress(mut self, compress: bool) -> BotBuilder {
+        self.config.compress = compress;
+        self
+    }
+
+    /// Drops the built-in commands and event handlers
+    pub fn without_default_commands(mut self) -> BotBuilder {
+        self.registry = CommandRegistry {
+            commands: vec![],
+            event_handlers: vec![],
+        };
+        self
+    }
+
+    pub fn command(mut self, command: Command) -> BotBuilder {
+        self.registry.commands.push(command);
+        self
+    }
+
+    pub fn event_handler(mut self, event_handler: EventHandler) -> BotBuilder {
+        self.registry.event_handlers.push(event_handler);
+        self
+    }
+
+    pub fn build(self) -> Bot {
+        let (shutdown, shutdown_receiver) = watch::channel(false);
+        Bot {
+            config: self.config,
+            registry: self.registry,
+            shutdown: Arc::new(shutdown),
+            shutdown_receiver,
+        }
+    }
+}
@@ -1,25 +1,228 @@
 use std::error::Error;
 use std::fmt;
+use std::time::Duration;
 
+use reqwest::StatusCode;
+
+use crate::model::ApiErrorBody;
+
+/// JSON error code of Discord for an invalid token
+const UNAUTHORIZED_CODE: u32 = 40001;
+
+/// JSON error code of Discord for an account that has to be verified first
+const VERIFY_ACCOUNT_CODE: u32 = 40002;
+
+/// How much of a payload that could not be deserialized ends up in the error
+const PAYLOAD_SNIPPET_LENGTH: usize = 200;
+
+/// Everything that can go wrong in the bot, split by what the caller can do about it
 #[derive(Debug)]
-pub struct MyError {
-    details: String
+pub enum BotError {
+    /// The REST API answered with an error status
+    Http {
+        /// Method and path of the request
+        route: String,
+        status: StatusCode,
+        /// JSON error code of Discord, see https://discord.com/developers/docs/topics/opcodes-and-status-codes#json
+        code: Option<u32>,
+        message: String,
+        /// What was wrong with each field of the request, for validation errors (50035)
+        errors: Vec<String>,
+    },
+    /// The request never got an answer
+    Request(reqwest::Error),
+    /// The REST API kept answering 429 after all retries
+    RateLimited {
+        route: String,
+        retry_after: Duration,
+        global: bool,
+    },
+    /// JSON that does not look like what was expected
+    Deserialize {
+        what: String,
+        snippet: String,
+        source: serde_json::Error,
+    },
+    /// A button, select menu or option that the message does not have
+    ComponentNotFound(String),
+    /// Pepe did not update the message in time
+    InteractionTimeout,
+    /// The gateway connection ended and is not coming back
+    GatewayClosed { code: Option<u16>, reason: String },
+    /// A message the matcher picked did not have the expected content
+    MatcherParse(String),
+    /// Reading or writing a file failed
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// A compressed gateway package could not be inflated
+    Decompress(String),
+}
+
+impl BotError {
+    /// Reads the error body Discord sent with an error status
+    pub fn http(route: String, status: StatusCode, body: &str) -> BotError {
+        match serde_json::from_str::<ApiErrorBody>(body) {
+            Ok(body) => {
+                let mut errors = vec![];
+                if let Some(field_errors) = &body.errors {
+                    collect_field_errors(field_errors, "", &mut errors);
+                }
+                BotError::Http {
+                    route,
+                    status,
+                    code: Some(body.code),
+                    message: body.message,
+                    errors,
+                }
+            }
+            // Proxies in front of Discord answer with HTML
+            Err(_) => BotError::Http {
+                route,
+                status,
+                code: None,
+                message: snippet(body),
+                errors: vec![],
+            },
+        }
+    }
+
+    /// Keeps the start of the payload, enough to recognize it in the logs
+    pub fn deserialize(what: &str, payload: &str, source: serde_json::Error) -> BotError {
+        BotError::Deserialize {
+            what: what.to_string(),
+            snippet: snippet(payload),
+            source,
+        }
+    }
+
+    pub fn io(path: &std::path::Path, source: std::io::Error) -> BotError {
+        BotError::Io {
+            path: path.display().to_string(),
+            source,
+        }
+    }
+
+    /// The token is invalid or Discord wants the account verified, a missing permission in
+    /// one channel (403) does not count
+    pub fn is_auth(&self) -> bool {
+        matches!(
+            self,
+            BotError::Http { status, code, .. }
+                if *status == StatusCode::UNAUTHORIZED
+                    || matches!(code, Some(UNAUTHORIZED_CODE | VERIFY_ACCOUNT_CODE))
+        )
+    }
+}
+
+/// Start of a payload, enough to recognize it in the logs
+fn snippet(payload: &str) -> String {
+    match payload.char_indices().nth(PAYLOAD_SNIPPET_LENGTH) {
+        Some((end, _)) => format!("{}...", &payload[..end]),
+        None => payload.to_string(),
+    }
 }
 
-impl MyError {
-    pub fn new(msg: &str) -> MyError {
-        MyError{details: msg.to_string()}
+/// Flattens the nested `errors` of a validation error into `field.path: message`
+///
+/// Every level is keyed by a field name or array index, the leaves are `_errors` arrays.
+fn collect_field_errors(errors: &serde_json::Value, path: &str, collected: &mut Vec<String>) {
+    let fields = match errors.as_object() {
+        Some(fields) => fields,
+        None => return,
+    };
+    for (field, value) in fields {
+        if field == "_errors" {
+            for error in value.as_array().into_iter().flatten() {
+                let message = error["message"].as_str().unwrap_or_default();
+                collected.push(format!("{}: {}", path, message));
+            }
+        } else if path.is_empty() {
+            collect_field_errors(value, field, collected);
+        } else {
+            collect_field_errors(value, &format!("{}.{}", path, field), collected);
+        }
     }
 }
 
-impl fmt::Display for MyError {
+impl fmt::Display for BotError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,"{}",self.details)
+        match self {
+            BotError::Http {
+                route,
+                status,
+                code,
+                message,
+                errors,
+            } => {
+                write!(f, "{} failed with {}: {}", route, status, message)?;
+                if let Some(code) = code {
+                    write!(f, " ({})", code)?;
+                }
+                for error in errors {
+                    write!(f, ", {}", error)?;
+                }
+                Ok(())
+            }
+            BotError::Request(error) => write!(f, "Request failed: {}", error),
+            BotError::RateLimited {
+                route,
+                retry_after,
+                global,
+            } => write!(
+                f,
+                "Rate limited{} on {}, retry after {:?}",
+                if *global { " globally" } else { "" },
+                route,
+                retry_after
+            ),
+            BotError::Deserialize {
+                what,
+                snippet,
+                source,
+            } => write!(f, "Could not read {}: {} in {}", what, source, snippet),
+            BotError::ComponentNotFound(component) => write!(f, "{} not found", component),
+            BotError::InteractionTimeout => write!(f, "Message was not updated in time"),
+            BotError::GatewayClosed {
+                code: Some(code),
+                reason,
+            } => write!(f, "Gateway closed with {}: {}", code, reason),
+            BotError::GatewayClosed { code: None, reason } => {
+                write!(f, "Gateway closed: {}", reason)
+            }
+            BotError::MatcherParse(details) => write!(f, "Unexpected message: {}", details),
+            BotError::Io { path, source } => write!(f, "Could not access {}: {}", path, source),
+            BotError::Decompress(details) => {
+                write!(f, "Could not inflate gateway package: {}", details)
+            }
+        }
+    }
+}
+
+impl Error for BotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BotError::Request(error) => Some(error),
+            BotError::Deserialize { source, .. } => Some(source),
+            BotError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for BotError {
+    fn from(error: reqwest::Error) -> Self {
+        BotError::Request(error)
     }
 }
 
-impl Error for MyError {
-    fn description(&self) -> &str {
-        &self.details
+/// Only happens when the client is gone
+impl<T> From<async_channel::SendError<T>> for BotError {
+    fn from(_: async_channel::SendError<T>) -> Self {
+        BotError::GatewayClosed {
+            code: None,
+            reason: "Client stopped".to_string(),
+        }
     }
-}
\ No newline at end of file
+}
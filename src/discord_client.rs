@@ -1,26 +1,35 @@
 extern crate futures;
 
-use crate::discord_commands;
-use crate::discord_commands::Command;
+use crate::custom_error::BotError;
+use crate::discord_commands::{Command, CommandRegistry, EventHandler};
 use crate::discord_message::*;
+use crate::gateway_writer::GatewayWriter;
 use crate::model::*;
+use crate::recorder::Recorder;
+use crate::rest::{post, RateLimiter};
+use crate::zlib_stream::ZlibStream;
 
-use async_recursion::async_recursion;
+use futures::future::BoxFuture;
 use futures::lock::Mutex;
-use futures::SinkExt;
 use futures::StreamExt;
 use log::warn;
 use rand::Rng;
 use reqwest::header::HeaderMap;
 use reqwest::Client;
-use serde::Serialize;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use std::time::Instant;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{watch, Notify};
 use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::Message;
 
-use log::{debug, error, info};
+use log::{debug, error, info, trace};
 
 fn make_http_client(token: &String) -> Client {
     let mut headers = HeaderMap::new();
@@ -30,78 +39,373 @@ fn make_http_client(token: &String) -> Client {
     Client::builder().default_headers(headers).build().unwrap()
 }
 
-#[async_recursion]
-pub async fn connect(token: String, master_id: Option<String>, channel_id: Option<String>) {
-    let (stream, _) = tokio_tungstenite::connect_async_tls_with_config(
-        "wss://gateway.discord.gg/?v=9&encoding=json",
-        None,
-        None,
-    )
-    .await
-    .expect("Failed to connect with the Discord Gateway");
+/// Discord Gateway used when no other url is configured
+pub const DEFAULT_GATEWAY_URL: &str = "wss://gateway.discord.gg";
+/// Discord REST API used when no other url is configured
+pub const DEFAULT_API_URL: &str = "https://discord.com/api/v9";
+/// Reconnect attempts in a row before giving up when nothing else is configured
+pub const DEFAULT_MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Base delay between reconnect attempts, doubled for every failed attempt
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+/// Longest delay between two reconnect attempts
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// How long running handlers get to finish when shutting down
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(15);
+/// How long the writer gets to send the close frame
+const WRITER_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
-    info!("Connected to the Discord WebSocket");
+/// Work left over after a package was handled, like the commands it matched
+///
+/// Runs concurrently, so a long flow does not hold up the packages after it.
+pub type FollowUp = BoxFuture<'static, ()>;
+
+pub struct ClientConfig {
+    /// Token of the discord account to use
+    pub token: String,
+    /// The master of this bot (can control the bot)
+    pub master_id: Option<String>,
+    /// The default channel in which the bot runs
+    pub channel_id: Option<String>,
+    /// How many times in a row to try reconnecting before giving up, 0 never gives up
+    pub max_reconnect_attempts: u32,
+    /// File to keep the farming channel and command cooldowns in between runs
+    pub state_file: Option<PathBuf>,
+    /// Base url of the Discord Gateway (e.g. wss://gateway.discord.gg)
+    pub gateway_url: String,
+    /// Base url of the Discord REST API (e.g. https://discord.com/api/v9)
+    pub api_url: String,
+    /// JSONL file to record the gateway and REST traffic to
+    pub record: Option<PathBuf>,
+    /// Log the requests to the REST API instead of sending them
+    pub dry_run: bool,
+    /// Ask the gateway for zlib-stream transport compression
+    pub compress: bool,
+}
 
-    if master_id.is_none() {
+/// Keeps the bot connected to the Discord Gateway
+///
+/// Only returns when reconnecting is not possible anymore or `shutdown` flips to true
+pub async fn connect(
+    config: ClientConfig,
+    registry: CommandRegistry,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), BotError> {
+    if config.master_id.is_none() {
         warn!("Does not have a master!");
         warn!("Will only listen to self");
     }
 
-    let (sink, stream) = stream.split();
-    let (message_update_sender, message_update_receiver) =
-        async_channel::unbounded::<DiscordMessage>();
+    let state = match &config.state_file {
+        Some(state_file) => load_state(state_file),
+        None => PersistedState::default(),
+    };
+
+    let rest_mode = if config.dry_run {
+        warn!("Dry run, nothing is sent to Discord");
+        RestMode::DryRun
+    } else {
+        RestMode::Live
+    };
+
+    let (shared_client, master_command_receiver) =
+        create_client(&config, registry, &state, rest_mode)?;
+
+    let shared_channel_id: Arc<Mutex<Option<String>>> =
+        Arc::new(Mutex::new(config.channel_id.clone().or(state.channel_id)));
+
+    let mut attempt: u32 = 0;
+
+    let result = loop {
+        // Also catches a shutdown from before connect was called
+        if *shutdown.borrow() {
+            break Ok(());
+        }
+        set_connection_state(&shared_client, ConnectionState::Connecting);
+        // Replaces the backoff when Discord wants the next connection to wait
+        let mut next_delay = None;
+
+        // A session is resumed on the gateway READY named for it
+        let gateway_url = match get_session(&shared_client).and_then(|s| s.resume_gateway_url) {
+            Some(resume_gateway_url) => gateway_query(&resume_gateway_url, config.compress),
+            None => gateway_query(&config.gateway_url, config.compress),
+        };
+        debug!("Connecting to {}", gateway_url);
+
+        let connection = tokio::select! {
+            connection = tokio_tungstenite::connect_async_tls_with_config(
+                &gateway_url,
+                None,
+                None,
+            ) => connection,
+            _ = shutdown.changed() => break Ok(()),
+        };
+
+        match connection {
+            Ok((stream, _)) => {
+                info!("Connected to the Discord WebSocket");
+
+                let close_code = run_connection(
+                    shared_client.clone(),
+                    stream,
+                    shared_channel_id.clone(),
+                    master_command_receiver.clone(),
+                    shutdown.clone(),
+                )
+                .await;
+
+                info!("Disconnected from the Discord Gateway");
+
+                if *shutdown.borrow() {
+                    break Ok(());
+                }
+
+                // A connection that got ready was healthy, so start counting again
+                if get_connection_state(&shared_client) == ConnectionState::Ready {
+                    attempt = 0;
+                }
+
+                if let Some(code) = close_code {
+                    match GatewayCloseCode::from_code(code) {
+                        Some(code) if code.is_fatal() => {
+                            error!(
+                                "Discord closed the connection with {} ({})",
+                                code as u16, code
+                            );
+                            break Err(BotError::GatewayClosed {
+                                code: Some(code as u16),
+                                reason: format!("{}, can not reconnect", code),
+                            });
+                        }
+                        Some(code) if code.requires_new_session() => {
+                            warn!(
+                                "Discord closed the connection with {} ({}), identifying with a new session",
+                                code as u16, code
+                            );
+                            reset_session(&shared_client);
+                            next_delay = Some(new_session_delay(code));
+                        }
+                        Some(code) => info!("Connection closed with {} ({})", code as u16, code),
+                        None => info!("Connection closed with {}", code),
+                    }
+                }
+            }
+            Err(error) => {
+                error!("Failed to connect with the Discord Gateway: {}", error);
+            }
+        }
+
+        set_connection_state(&shared_client, ConnectionState::Disconnected);
+
+        attempt += 1;
+        if config.max_reconnect_attempts > 0 && attempt > config.max_reconnect_attempts {
+            break Err(BotError::GatewayClosed {
+                code: None,
+                reason: format!(
+                    "Gave up reconnecting after {} attempts",
+                    config.max_reconnect_attempts
+                ),
+            });
+        }
+
+        let delay = next_delay.unwrap_or_else(|| reconnect_delay(attempt));
+        if config.max_reconnect_attempts > 0 {
+            info!(
+                "Trying to reconnect in {:?} (attempt {}/{})",
+                delay, attempt, config.max_reconnect_attempts
+            );
+        } else {
+            info!("Trying to reconnect in {:?} (attempt {})", delay, attempt);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.changed() => break Ok(()),
+        }
+    };
+
+    set_connection_state(&shared_client, ConnectionState::Disconnected);
+
+    // Saved on every way out, also when Discord does not let the bot back in
+    // Nothing was sent during a dry run, so its cooldowns would be made up
+    if let (Some(state_file), false) = (&config.state_file, config.dry_run) {
+        save_state(state_file, &shared_client, &shared_channel_id).await;
+    }
+
+    info!("Stopped");
+    result
+}
+
+/// Creates the client and the channels connecting it with the connection
+pub fn create_client(
+    config: &ClientConfig,
+    registry: CommandRegistry,
+    state: &PersistedState,
+    rest_mode: RestMode,
+) -> Result<(SharedDiscordClient, async_channel::Receiver<MasterCommand>), BotError> {
+    let recorder = match &config.record {
+        Some(path) => {
+            info!("Recording traffic to {}", path.display());
+            Some(Arc::new(Recorder::open(path, &config.token)?))
+        }
+        None => None,
+    };
+
     let (master_command_sender, master_command_receiver) =
         async_channel::unbounded::<MasterCommand>();
 
-    let shared_client: SharedDiscordClient = Arc::new(Mutex::new(DiscordClient {
-        http: make_http_client(&token),
-        master_id: master_id.clone(),
-        session_id: "".to_owned(),
-        token: token.to_string(),
-        user: None,
-        sequence: 0,
-        websocket_writer: sink,
-        message_update_receiver,
-        master_command_sender: master_command_sender.clone(),
-        commands: discord_commands::get_commands(),
-    }));
-    let shared_client_clone = shared_client.clone();
+    let shared_client: SharedDiscordClient = Arc::new(DiscordClient {
+        http: make_http_client(&config.token),
+        api_url: config.api_url.trim_end_matches('/').to_string(),
+        recorder,
+        rest_mode,
+        master_id: config.master_id.clone(),
+        token: config.token.to_string(),
+        message_updates: MessageSubscriptions::default(),
+        message_reactions: MessageSubscriptions::default(),
+        master_command_sender,
+        registry,
+        rate_limits: RateLimiter::default(),
+        sequence: AtomicU64::new(0),
+        session_id: RwLock::new(String::new()),
+        resume_gateway_url: RwLock::new(None),
+        user: RwLock::new(None),
+        gateway_writer: RwLock::new(None),
+        connection: std::sync::Mutex::new(Connection::default()),
+        cooldowns: std::sync::Mutex::new(
+            state
+                .last_called
+                .iter()
+                .filter_map(|(command, last_called)| {
+                    let last_called = UNIX_EPOCH + Duration::from_secs(*last_called);
+                    let elapsed = SystemTime::now().duration_since(last_called).ok()?;
+                    Some((command.to_string(), Instant::now().checked_sub(elapsed)?))
+                })
+                .collect(),
+        ),
+    });
+
+    Ok((shared_client, master_command_receiver))
+}
 
-    let shared_channel_id: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(channel_id.clone()));
+fn load_state(state_file: &Path) -> PersistedState {
+    match std::fs::read_to_string(state_file) {
+        Ok(json) => match serde_json::from_str(&json) {
+            Ok(state) => {
+                info!("Loaded state from {}", state_file.display());
+                state
+            }
+            Err(error) => {
+                warn!(
+                    "Ignoring invalid state in {}: {}",
+                    state_file.display(),
+                    error
+                );
+                PersistedState::default()
+            }
+        },
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => PersistedState::default(),
+        Err(error) => {
+            warn!(
+                "Could not read state from {}: {}",
+                state_file.display(),
+                error
+            );
+            PersistedState::default()
+        }
+    }
+}
+
+async fn save_state(
+    state_file: &Path,
+    shared_client: &SharedDiscordClient,
+    shared_channel_id: &Arc<Mutex<Option<String>>>,
+) {
+    let state = PersistedState {
+        channel_id: shared_channel_id.lock().await.clone(),
+        last_called: shared_client
+            .cooldowns
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(command, last_called)| {
+                let last_called = SystemTime::now().checked_sub(last_called.elapsed())?;
+                let seconds = last_called.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                Some((command.to_string(), seconds))
+            })
+            .collect(),
+    };
+
+    match std::fs::write(state_file, serde_json::to_string_pretty(&state).unwrap()) {
+        Ok(()) => info!("Saved state to {}", state_file.display()),
+        Err(error) => error!(
+            "Could not save state to {}: {}",
+            state_file.display(),
+            error
+        ),
+    }
+}
+
+/// Runs a single gateway connection until it closes and returns the close code
+async fn run_connection(
+    shared_client: SharedDiscordClient,
+    stream: WebSocketStream,
+    shared_channel_id: Arc<Mutex<Option<String>>>,
+    master_command_receiver: async_channel::Receiver<MasterCommand>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Option<u16> {
+    let (sink, mut stream) = stream.split();
+    let (writer, writer_task) = GatewayWriter::spawn(sink);
+    let disconnect = Arc::new(Notify::new());
+
+    *shared_client.gateway_writer.write().unwrap() = Some(writer);
+    {
+        let mut connection = shared_client.connection();
+        connection.disconnect = disconnect.clone();
+        connection.heartbeat_acked = true;
+        connection.last_heartbeat = None;
+    }
+
+    let shared_client_clone = shared_client.clone();
+    let recorder = shared_client.recorder.clone();
     let shared_channel_id_clone = shared_channel_id.clone();
 
     let command_loop = tokio::spawn(async move {
-        let mut tokio_thread: Option<JoinHandle<()>> = None;
-
         info!("Listening for Master Commands");
 
         loop {
             let master_command = master_command_receiver.recv().await.unwrap();
-            let is_running = &tokio_thread.is_some() == &true;
 
             match master_command.command {
                 MasterCommandType::Start => {
                     let channel_id = master_command.tag.unwrap().to_string();
                     *shared_channel_id_clone.clone().lock().await = Some(channel_id.to_string());
                     let mut interval = tokio::time::interval(Duration::from_secs(1));
-                    let shared_client_1 = shared_client.clone();
                     let shared_client_2 = shared_client.clone();
-                    let cmd_client = shared_client_1.lock().await;
-                    let mut runnable_commands = cmd_client
+                    let mut runnable_commands = shared_client
+                        .registry
                         .commands
-                        .clone()
-                        .into_iter()
+                        .iter()
                         .filter(|c| c.command.is_some())
+                        .cloned()
                         .collect::<Vec<Command>>();
+                    // Continue with the cooldowns of earlier connections
+                    let cooldowns = shared_client.cooldowns.lock().unwrap().clone();
+                    for command in runnable_commands.iter_mut() {
+                        let content = command.command.as_ref().unwrap();
+                        command.last_called = cooldowns.get(content).copied();
+                    }
                     let commands_length = runnable_commands.len();
 
-                    if !is_running {
+                    let mut connection = shared_client.connection();
+                    if runnable_commands.is_empty() {
+                        warn!("No commands to farm with, not running in {}", channel_id);
+                    } else if connection.farming.is_none() {
                         info!("Running in {}", channel_id.to_string());
-                        tokio_thread = Some(tokio::spawn(async move {
+                        connection.farming = Some(tokio::spawn(async move {
                             interval.tick().await;
                             loop {
                                 interval.tick().await;
-                                let mut command = &mut runnable_commands
+                                let command = &mut runnable_commands
                                     [rand::thread_rng().gen_range(0..commands_length)];
                                 if command.last_called.is_none()
                                     || command.last_called.unwrap().elapsed() >= command.cooldown
@@ -110,257 +414,598 @@ pub async fn connect(token: String, master_id: Option<String>, channel_id: Optio
                                     let command_content =
                                         command.command.as_ref().unwrap().to_string();
                                     shared_client_2
-                                        .clone()
+                                        .cooldowns
                                         .lock()
-                                        .await
-                                        .http
-                                        .post(format!(
-                                            "https://discord.com/api/v9/channels/{}/messages",
-                                            channel_id.to_string()
-                                        ))
-                                        .body(
-                                            serde_json::to_string(&DiscordMessagePayload {
-                                                content: command_content,
-                                                message_reference: None,
-                                            })
-                                            .unwrap(),
-                                        )
-                                        .send()
-                                        .await
-                                        .unwrap();
+                                        .unwrap()
+                                        .insert(command_content.to_string(), Instant::now());
+                                    let body = serde_json::to_string(&DiscordMessagePayload {
+                                        content: command_content.to_string(),
+                                        message_reference: None,
+                                    })
+                                    .unwrap();
+                                    let path = format!("/channels/{}/messages", channel_id);
+                                    if let Err(error) = post(&shared_client_2, &path, body).await {
+                                        let source = format!("Farming {}", command_content);
+                                        report_error(&shared_client_2, &source, error).await;
+                                    }
                                 }
                             }
                         }));
                     }
                 }
                 MasterCommandType::Stop => {
-                    if is_running {
-                        tokio_thread.as_ref().unwrap().abort();
-                        tokio_thread = None;
-                    }
-                }
-            }
-        }
-    });
-
-    let message_handler = stream.for_each(|result| async {
-        let mus = message_update_sender.clone();
-        match result {
-            Ok(message) => match &message {
-                Message::Text(json) => {
-                    let package: Result<Package, serde_json::Error> = serde_json::from_str(json);
-                    match package {
-                        Ok(package) => {
-                            let scc = shared_client_clone.clone();
-                            tokio::spawn(async move {
-                                handle_ws_package(scc.clone(), package, mus).await;
-                            });
-                        }
-                        Err(error) => {
-                            error!("Not JSON: {:#?}", json);
-                            error!("Error: {:#?}", error);
-                        }
+                    *shared_channel_id_clone.lock().await = None;
+                    let farming = shared_client.connection().farming.take();
+                    if let Some(farming) = farming {
+                        farming.abort();
                     }
                 }
-                _else => {
-                    error!("Received Unknown Message: {:#?}", message);
-                }
-            },
-            Err(error) => {
-                error!("Error Occurred: {:#?}", error);
             }
         }
     });
 
     // If channel id is known, start on this channel
-    if channel_id.is_some() {
-        master_command_sender
-            .clone()
+    let channel_id = shared_channel_id.lock().await.clone();
+    if let Some(channel_id) = channel_id {
+        shared_client_clone
+            .master_command_sender
             .send(MasterCommand {
                 command: MasterCommandType::Start,
-                tag: Some(channel_id.unwrap()),
+                tag: Some(channel_id),
             })
             .await
             .unwrap();
     }
 
-    // pin_mut!(message_handler);
-    // futures::future::select(message_handler, command_loop);
+    let mut close_code: Option<u16> = None;
+    let mut close_frame: Option<CloseFrame> = None;
+    let mut handlers: Vec<JoinHandle<()>> = vec![];
+    let mut command_loop = Some(command_loop);
+    // Set while shutting down, running handlers get until then to finish
+    let mut grace_deadline: Option<tokio::time::Instant> = None;
+    let mut grace_check = tokio::time::interval(Duration::from_millis(100));
+    // Only gets frames when the connection uses transport compression
+    let mut zlib_stream = ZlibStream::default();
 
-    message_handler.await;
+    loop {
+        let result = tokio::select! {
+            result = stream.next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            _ = disconnect.notified() => {
+                // Closing with a non 1000 code keeps the session alive, so it can be resumed
+                close_frame = Some(CloseFrame {
+                    code: CloseCode::from(4000),
+                    reason: "Reconnecting".into(),
+                });
+                break;
+            }
+            _ = shutdown.changed(), if grace_deadline.is_none() => {
+                // Stop farming right away, but keep reading so running handlers get their updates
+                stop_task(command_loop.take()).await;
+                let farming = {
+                    let mut connection = shared_client_clone.connection();
+                    connection.shutting_down = true;
+                    connection.farming.take()
+                };
+                stop_task(farming).await;
+
+                info!("Waiting for running handlers to finish");
+                grace_deadline = Some(tokio::time::Instant::now() + SHUTDOWN_GRACE_PERIOD);
+                close_frame = Some(CloseFrame {
+                    code: CloseCode::Normal,
+                    reason: "Shutting down".into(),
+                });
+                continue;
+            }
+            _ = grace_check.tick(), if grace_deadline.is_some() => {
+                handlers.retain(|handler| !handler.is_finished());
+                if handlers.is_empty() {
+                    break;
+                }
+                if grace_deadline.is_some_and(|deadline| deadline <= tokio::time::Instant::now()) {
+                    warn!("{} handlers did not finish in time", handlers.len());
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let json = match result {
+            Ok(Message::Text(json)) => json,
+            Ok(Message::Binary(frame)) => match zlib_stream.push(&frame) {
+                Ok(Some(json)) => json,
+                // The rest of the package is still on its way
+                Ok(None) => continue,
+                Err(error) => {
+                    // The shared zlib context is broken, nothing after this can be read
+                    error!("{}", error);
+                    close_frame = Some(CloseFrame {
+                        code: CloseCode::from(4000),
+                        reason: "Reconnecting".into(),
+                    });
+                    break;
+                }
+            },
+            Ok(Message::Close(frame)) => {
+                debug!("Close Frame: {:?}", frame);
+                close_code = frame.map(|frame| frame.code.into());
+                continue;
+            }
+            Ok(message) => {
+                error!("Received Unknown Message: {:#?}", message);
+                continue;
+            }
+            Err(error) => {
+                error!("Error Occurred: {:#?}", error);
+                continue;
+            }
+        };
+
+        if let Some(recorder) = &recorder {
+            recorder.record_package(&json);
+        }
+        let package: Result<Package, serde_json::Error> = serde_json::from_str(&json);
+        match package {
+            Ok(package) => {
+                // Handled before the next package is read, so the sequence and the
+                // update subscriptions of new messages are always applied in order
+                let follow_up = handle_ws_package(shared_client_clone.clone(), package).await;
+                if let Some(follow_up) = follow_up {
+                    handlers.retain(|handler| !handler.is_finished());
+                    handlers.push(tokio::spawn(follow_up));
+                }
+            }
+            Err(error) => {
+                error!("{}", BotError::deserialize("gateway package", &json, error));
+            }
+        }
+    }
 
-    info!("Disconnected from the Discord Gateway");
     info!("Closing threads");
-    command_loop.abort();
 
-    info!("Trying to reconnect...");
+    // Stop everything that belongs to this connection before letting go of the writer,
+    // so nothing can write to a dead connection
+    let (farming, heartbeat) = {
+        let mut connection = shared_client_clone.connection();
+        (connection.farming.take(), connection.heartbeat.take())
+    };
+    let tasks = [command_loop, farming, heartbeat]
+        .into_iter()
+        .chain(handlers.into_iter().map(Some));
+    for task in tasks {
+        stop_task(task).await;
+    }
+
+    let writer = shared_client_clone.gateway_writer.write().unwrap().take();
+    match (writer, close_frame) {
+        (Some(writer), Some(close_frame)) => {
+            writer.close(close_frame);
+            drop(writer);
+            if tokio::time::timeout(WRITER_CLOSE_TIMEOUT, writer_task)
+                .await
+                .is_err()
+            {
+                warn!("Could not send the close frame in time");
+            }
+        }
+        _ => stop_task(Some(writer_task)).await,
+    }
+
+    close_code
+}
+
+/// Aborts the task and waits until it is gone
+async fn stop_task(task: Option<JoinHandle<()>>) {
+    if let Some(task) = task {
+        task.abort();
+        task.await.ok();
+    }
+}
 
-    // Disconnected so try to reconnect
-    connect(
-        token.to_owned(),
-        master_id.to_owned(),
-        shared_channel_id.lock().await.clone(),
+/// Adds the version, encoding and compression to a gateway url
+fn gateway_query(gateway_url: &str, compress: bool) -> String {
+    format!(
+        "{}/?v=9&encoding=json{}",
+        gateway_url.trim_end_matches('/'),
+        if compress {
+            "&compress=zlib-stream"
+        } else {
+            ""
+        }
     )
-    .await;
+}
+
+/// Delay before the given reconnect attempt, doubles every attempt up to a maximum
+fn reconnect_delay(attempt: u32) -> Duration {
+    RECONNECT_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(RECONNECT_MAX_DELAY)
 }
 
 /// Handles a Discord WebSocket Package
-async fn handle_ws_package(
+///
+/// Everything that changes the client state happens before returning,
+/// what may take longer is returned as a follow-up for the caller to spawn.
+pub async fn handle_ws_package(
     shared_client: SharedDiscordClient,
     package: Package,
-    message_update_sender: async_channel::Sender<DiscordMessage>,
-) {
+) -> Option<FollowUp> {
     // Set the sequence if there is one in the package
-    match &package.sequence {
-        Some(sequence) => shared_client.clone().lock().await.sequence = *sequence,
-        None => {}
+    if let Some(sequence) = &package.sequence {
+        shared_client.sequence.store(*sequence, Ordering::Relaxed);
     }
 
     // Log the Package
     debug!(
         "{:?}. {:?}, has_data: {}",
-        shared_client.clone().lock().await.sequence,
+        get_sequence(&shared_client),
         package.op,
         package.data.is_some()
     );
 
     match &package.op {
         OpCode::Hello => {
-            let hello: HelloData = serde_json::from_value(package.data.unwrap()).unwrap();
-
-            interval_heartbeat(shared_client.clone(), hello.heartbeat_interval);
-
-            let data = IdentifyData {
-                token: get_token(&shared_client).await,
-                properties: Properties {
-                    browser: String::from("rust"),
-                    device: String::from("rust"),
-                    os: String::from(std::env::consts::OS),
-                },
-                compress: false,
-                presence: Presence {
-                    activities: vec![Activity {
-                        name: String::from("Dank Memer"),
-                        activity_type: 0,
-                    }],
-                    status: String::from("online"),
-                    since: 0,
-                    afk: false,
-                },
+            let data = package.data.clone().unwrap_or_default();
+            let hello = match HelloData::deserialize(&data) {
+                Ok(hello) => hello,
+                Err(error) => {
+                    // Without a heartbeat interval the connection can not be kept alive
+                    error!(
+                        "{}",
+                        BotError::deserialize("Hello", &data.to_string(), error)
+                    );
+                    shared_client.connection().disconnect.notify_one();
+                    return None;
+                }
             };
-            // Login
-            dispatch(shared_client.clone(), OpCode::Identify, &data, None).await;
+
+            let heartbeat = interval_heartbeat(shared_client.clone(), hello.heartbeat_interval);
+            let previous = shared_client.connection().heartbeat.replace(heartbeat);
+            if let Some(previous) = previous {
+                previous.abort();
+            }
+
+            match get_session(&shared_client) {
+                Some(session) => {
+                    info!("Resuming session {}", &session.session_id);
+                    set_connection_state(&shared_client, ConnectionState::Resuming);
+
+                    let data = ResumeData {
+                        token: get_token(&shared_client),
+                        session_id: session.session_id,
+                        seq: session.sequence,
+                    };
+                    dispatch(shared_client.clone(), OpCode::Resume, data, None).await;
+                }
+                None => identify(shared_client.clone()).await,
+            }
+            None
         }
         OpCode::Heartbeat => {
-            dispatch(
-                shared_client.clone(),
-                OpCode::Heartbeat,
-                get_sequence(&shared_client).await,
-                None,
-            )
-            .await;
+            heartbeat(shared_client.clone()).await;
+            None
         }
         OpCode::Reconnect => {
             debug!("Reconnect: {:#?}", package);
 
-            let client = shared_client.clone();
-            let client = client.lock().await;
+            // The next connection will resume the session
+            shared_client.connection().disconnect.notify_one();
+            None
+        }
+        OpCode::InvalidSession => {
+            let resumable: bool = package
+                .data
+                .and_then(|data| data.as_bool())
+                .unwrap_or(false);
 
-            let data = ResumeData {
-                token: client.token.clone(),
-                session_id: client.session_id.clone(),
-                seq: client.sequence,
-            };
+            match get_session(&shared_client) {
+                // The connection is closing anyway, a new session would only be thrown away
+                _ if shared_client.connection().shutting_down => None,
+                Some(session) if resumable => {
+                    warn!("Session is invalid, resuming again");
 
-            dispatch(shared_client.clone(), OpCode::Resume, data, None).await;
+                    let data = ResumeData {
+                        token: get_token(&shared_client),
+                        session_id: session.session_id,
+                        seq: session.sequence,
+                    };
+                    dispatch(shared_client.clone(), OpCode::Resume, data, None).await;
+                    None
+                }
+                _ => {
+                    let delay = Duration::from_millis(rand::thread_rng().gen_range(1000..=5000));
+                    warn!("Session is invalid, identifying again in {:?}", delay);
+
+                    reset_session(&shared_client);
+
+                    // Discord wants a random wait between 1 and 5 seconds
+                    Some(Box::pin(async move {
+                        tokio::time::sleep(delay).await;
+                        identify(shared_client).await;
+                    }))
+                }
+            }
+        }
+        OpCode::HeartbeatAck => {
+            let mut connection = shared_client.connection();
+            connection.heartbeat_acked = true;
+            connection.latency = connection.last_heartbeat.map(|sent| sent.elapsed());
+            debug!("Heartbeat acknowledged, latency: {:?}", connection.latency);
+            None
         }
-        OpCode::HeartbeatAck => {}
         OpCode::Dispatch => {
-            let event = package.tag.unwrap();
-            let json_data = package.data.unwrap();
-            match event.as_str() {
-                "READY" => {
-                    // std::fs::write("data/ready.json", json_data.to_string()).expect("Unable to write file");
+            let name = match package.tag {
+                Some(name) => name,
+                None => {
+                    warn!("Dispatch without an event name");
+                    return None;
+                }
+            };
+            match GatewayEvent::parse(&name, package.data.unwrap_or_default()) {
+                Ok(event) => handle_event(shared_client, event).await,
+                Err(error) => {
+                    error!("{}", error);
+                    None
+                }
+            }
+        }
+        _other => {
+            debug!("Unhandled OpCode: {:?}", package.op);
+            None
+        }
+    }
+}
 
-                    let ready: ReadyData = serde_json::from_value(json_data).unwrap();
+/// Handles a Dispatch of the gateway and picks the commands subscribed to it
+///
+/// The matchers run right away, the commands and event handlers they picked run in the follow-up.
+async fn handle_event(shared_client: SharedDiscordClient, event: GatewayEvent) -> Option<FollowUp> {
+    match &event {
+        GatewayEvent::Ready(ready) => {
+            info!("Logged in as {}", &ready.user.username);
 
-                    info!("Logged in as {}", &ready.user.username);
+            *shared_client.session_id.write().unwrap() = ready.session_id.to_string();
+            *shared_client.resume_gateway_url.write().unwrap() = ready.resume_gateway_url.clone();
+            *shared_client.user.write().unwrap() = Some(ready.user.clone());
+            set_connection_state(&shared_client, ConnectionState::Ready);
+        }
+        GatewayEvent::Resumed => {
+            info!("Resumed session");
+            set_connection_state(&shared_client, ConnectionState::Ready);
+        }
+        GatewayEvent::MessageUpdate(data) => {
+            shared_client.message_updates.send(&data.id, data.clone());
+        }
+        GatewayEvent::MessageDelete(data) => {
+            debug!("Message {} deleted in {}", data.id, data.channel_id)
+        }
+        GatewayEvent::MessageReactionAdd(data) => {
+            debug!(
+                "Reaction {} added to message {} by {}",
+                data.emoji, data.message_id, data.user_id
+            );
+            shared_client
+                .message_reactions
+                .send(&data.message_id, data.clone());
+        }
+        GatewayEvent::MessageReactionRemove(data) => {
+            debug!(
+                "Reaction {} removed from message {} by {}",
+                data.emoji, data.message_id, data.user_id
+            )
+        }
+        GatewayEvent::InteractionCreate(data) => debug!("Interaction {} created", data.id),
+        GatewayEvent::InteractionSuccess(data) => debug!("Interaction {} succeeded", data.id),
+        GatewayEvent::InteractionFailure(data) => debug!("Interaction {} failed", data.id),
+        GatewayEvent::SessionsReplace(sessions) => {
+            debug!("{} sessions active", sessions.len())
+        }
+        GatewayEvent::GuildCreate(guild) => debug!("Guild {} available", guild.id),
+        GatewayEvent::ChannelCreate(channel)
+        | GatewayEvent::ChannelUpdate(channel)
+        | GatewayEvent::ChannelDelete(channel) => {
+            debug!("{:?} for channel {}", event.kind(), channel.id)
+        }
+        GatewayEvent::Unknown { name, data } => {
+            debug!("Unhandled event: {}", name);
+            trace!("{}", data);
+        }
+        GatewayEvent::MessageCreate(_) => {}
+    }
 
-                    let arc = shared_client.clone();
-                    let mut client = arc.lock().await;
-                    client.session_id = ready.session_id;
-                    client.user = Some(ready.user);
-                }
-                "MESSAGE_CREATE" => {
-                    // std::fs::write("data/message_create.json", json_data.to_string()).expect("Unable to write file");
-
-                    let data = serde_json::from_value::<MessageCreateData>(json_data);
-                    match data {
-                        Ok(data) => {
-                            let message: DiscordMessage =
-                                DiscordMessage::new(data, shared_client.clone()).await;
-                            let client = shared_client.clone();
-                            let client = client.lock().await;
-
-                            let commands = client.commands.clone();
-                            drop(client);
-
-                            // Get command handler
-                            let handlers = commands
-                                .into_iter()
-                                .filter(|handler| (handler.matcher)(&message));
-                            for handler in handlers {
-                                let result = (handler.execute)(&message).await;
-                                match result {
-                                    Ok(()) => {}
-                                    Err(error) => error!("Error in Command {:#?}", error),
-                                }
-                            }
-                        }
-                        Err(error) => {
-                            error!("Error: {:#?}", error);
-                        }
-                    }
-                }
-                "MESSAGE_UPDATE" => {
-                    match serde_json::from_value::<MessageCreateData>(json_data.clone()) {
-                        Ok(data) => {
-                            let message: DiscordMessage = DiscordMessage::new(data, shared_client).await;
+    // Updates still reach the running handlers, but nothing new is started
+    if shared_client.connection().shutting_down {
+        return None;
+    }
 
-                            message_update_sender.clone().send(message).await.unwrap();
-                        }
-                        Err(e) => {
-                            error!("Error: {:#?}", e);
-                            error!("Object: {:#?}", json_data);
-                        }
-                    };
-                    
+    let kind = event.kind();
+    let registry = &shared_client.registry;
+
+    // Created here, so the message is subscribed to updates before the next package is handled
+    let message = event.message().and_then(|data| {
+        let message = DiscordMessage::new(data.clone(), shared_client.clone());
+        if message.is_none() {
+            warn!("Ignoring message {}, not logged in yet", data.id);
+        }
+        message
+    });
+    let commands: Vec<Command> = match &message {
+        Some(message) => registry
+            .commands
+            .iter()
+            .filter(|handler| handler.event == kind && (handler.matcher)(message))
+            .cloned()
+            .collect(),
+        None => vec![],
+    };
+    let event_handlers: Vec<EventHandler> = registry
+        .event_handlers
+        .iter()
+        .filter(|handler| handler.event == kind && (handler.matcher)(&event))
+        .cloned()
+        .collect();
+
+    if commands.is_empty() && event_handlers.is_empty() {
+        return None;
+    }
+
+    Some(Box::pin(async move {
+        if let Some(message) = message {
+            for handler in commands {
+                // Not run again on errors, the requests that already went out would be repeated
+                if let Err(error) = (handler.execute)(&message).await {
+                    report_error(&shared_client, "Command", error).await;
                 }
-                "SESSION_REPLACE" => {}
-                "PRESENCE_UPDATE" => {}
-                "SESSIONS_REPLACE" => {}
-                "INTERACTION_CREATE" => {}
-                "INTERACTION_SUCCESS" => {}
-                "MESSAGE_ACK" => {}
-                _ => debug!("Unhandled event: {}", event),
             }
         }
-        _other => debug!("Unhandled OpCode: {:?}", package.op),
+        for handler in event_handlers {
+            if let Err(error) = (handler.execute)(&event, &shared_client).await {
+                report_error(&shared_client, "Event Handler", error).await;
+            }
+        }
+    }))
+}
+
+/// Logs the error of a handler
+///
+/// Discord refusing the account will not get better by itself, so farming stops until the master
+/// starts it again and the master is told why.
+async fn report_error(shared_client: &SharedDiscordClient, source: &str, error: BotError) {
+    if !error.is_auth() {
+        error!("Error in {}: {}", source, error);
+        return;
+    }
+    error!(
+        "{} was refused by Discord, stopping until the master starts farming again: {}",
+        source, error
+    );
+    let alert = format!("Stopped farming, Discord refused the account: {}", error);
+    if let Err(error) = notify_master(shared_client, &alert).await {
+        error!("Could not tell the master: {}", error);
     }
+    shared_client
+        .master_command_sender
+        .send(MasterCommand {
+            command: MasterCommandType::Stop,
+            tag: None,
+        })
+        .await
+        .ok();
 }
 
-async fn get_sequence(shared_client: &SharedDiscordClient) -> u64 {
-    shared_client.clone().lock().await.sequence
+/// Sends the master a direct message
+async fn notify_master(shared_client: &DiscordClient, content: &str) -> Result<(), BotError> {
+    let master_id = match &shared_client.master_id {
+        Some(master_id) => master_id.to_string(),
+        None => return Ok(()),
+    };
+    let body = serde_json::to_string(&DirectMessagePayload {
+        recipient_id: master_id,
+    })
+    .unwrap();
+    let response = post(shared_client, "/users/@me/channels", body).await?;
+    let text = response.text().await?;
+    let channel: ChannelData = serde_json::from_str(&text)
+        .map_err(|error| BotError::deserialize("direct message channel", &text, error))?;
+
+    let body = serde_json::to_string(&DiscordMessagePayload {
+        content: content.to_string(),
+        message_reference: None,
+    })
+    .unwrap();
+    post(
+        shared_client,
+        &format!("/channels/{}/messages", channel.id),
+        body,
+    )
+    .await?;
+    Ok(())
 }
 
-async fn get_token(shared_client: &SharedDiscordClient) -> String {
-    shared_client.clone().lock().await.token.to_string()
+/// How long to wait before identifying again after the gateway dropped the session
+fn new_session_delay(code: GatewayCloseCode) -> Duration {
+    match code {
+        // The gateway allows 120 events every 60 seconds, so wait for a fresh window
+        GatewayCloseCode::RateLimited => Duration::from_secs(60),
+        _ => Duration::from_millis(rand::thread_rng().gen_range(1000..=5000)),
+    }
 }
 
-fn interval_heartbeat(shared_client: SharedDiscordClient, heartbeat_interval: u64) {
-    let mut interval = tokio::time::interval(Duration::from_millis(heartbeat_interval));
+/// Identifies as a new session
+async fn identify(shared_client: SharedDiscordClient) {
+    set_connection_state(&shared_client, ConnectionState::Identifying);
+
+    let data = IdentifyData {
+        token: get_token(&shared_client),
+        properties: Properties {
+            browser: String::from("rust"),
+            device: String::from("rust"),
+            os: String::from(std::env::consts::OS),
+        },
+        // Payload compression, can not be combined with the transport compression of zlib-stream
+        compress: false,
+        presence: Presence {
+            activities: vec![Activity {
+                name: String::from("Dank Memer"),
+                activity_type: 0,
+            }],
+            status: String::from("online"),
+            since: 0,
+            afk: false,
+        },
+    };
+    // Login
+    dispatch(shared_client, OpCode::Identify, &data, None).await;
+}
+
+/// Returns the current session if it can be resumed
+fn get_session(shared_client: &SharedDiscordClient) -> Option<Session> {
+    let session_id = shared_client.session_id();
+    if shared_client.user.read().unwrap().is_some() && !session_id.is_empty() {
+        Some(Session {
+            session_id,
+            sequence: get_sequence(shared_client),
+            resume_gateway_url: shared_client.resume_gateway_url.read().unwrap().clone(),
+        })
+    } else {
+        None
+    }
+}
+
+/// Forgets the current session, the next Hello will identify again
+fn reset_session(shared_client: &SharedDiscordClient) {
+    shared_client.session_id.write().unwrap().clear();
+    shared_client.resume_gateway_url.write().unwrap().take();
+    shared_client.sequence.store(0, Ordering::Relaxed);
+}
 
-    let client = shared_client.clone();
+pub fn get_connection_state(shared_client: &SharedDiscordClient) -> ConnectionState {
+    shared_client.connection().state
+}
+
+fn set_connection_state(shared_client: &SharedDiscordClient, state: ConnectionState) {
+    let mut connection = shared_client.connection();
+    if connection.state != state {
+        debug!("Connection state: {:?} -> {:?}", connection.state, state);
+        connection.state = state;
+    }
+}
+
+fn get_sequence(shared_client: &SharedDiscordClient) -> u64 {
+    shared_client.sequence.load(Ordering::Relaxed)
+}
+
+fn get_token(shared_client: &SharedDiscordClient) -> String {
+    shared_client.token.to_string()
+}
+
+fn interval_heartbeat(
+    shared_client: SharedDiscordClient,
+    heartbeat_interval: u64,
+) -> JoinHandle<()> {
+    let mut interval = tokio::time::interval(Duration::from_millis(heartbeat_interval));
 
     tokio::spawn(async move {
         interval.tick().await;
@@ -368,40 +1013,57 @@ fn interval_heartbeat(shared_client: SharedDiscordClient, heartbeat_interval: u6
         loop {
             interval.tick().await;
 
-            dispatch(
-                client.clone(),
-                OpCode::Heartbeat,
-                get_sequence(&shared_client).await,
-                None,
-            )
-            .await;
+            {
+                let connection = shared_client.connection();
+                if !connection.heartbeat_acked {
+                    // No ack since the last heartbeat, so the connection is dead without being closed
+                    warn!("Heartbeat was not acknowledged, reconnecting");
+                    connection.disconnect.notify_one();
+                    break;
+                }
+            }
+
+            heartbeat(shared_client.clone()).await;
         }
-    });
+    })
+}
+
+async fn heartbeat(shared_client: SharedDiscordClient) {
+    {
+        let mut connection = shared_client.connection();
+        connection.heartbeat_acked = false;
+        connection.last_heartbeat = Some(Instant::now());
+    }
+    let sequence = get_sequence(&shared_client);
+
+    dispatch(shared_client, OpCode::Heartbeat, sequence, None).await;
+}
+
+/// Round-trip time of the last acknowledged heartbeat
+pub fn get_latency(shared_client: &SharedDiscordClient) -> Option<Duration> {
+    shared_client.connection().latency
 }
 
-async fn dispatch<'a, T>(
+async fn dispatch<T>(
     shared_client: SharedDiscordClient,
     op_code: OpCode,
     data: T,
-    event: Option<&'a str>,
+    event: Option<&str>,
 ) where
     T: Serialize,
 {
     let package = PackageWithData::<T> {
         op: op_code,
         d: data,
-        t: match event {
-            Some(evt) => Some(evt.to_string()),
-            None => None,
-        },
-        s: Some(get_sequence(&shared_client).await),
+        t: event.map(|evt| evt.to_string()),
+        s: Some(get_sequence(&shared_client)),
     };
-    shared_client
-        .clone()
-        .lock()
-        .await
-        .websocket_writer
-        .send(Message::text(&serde_json::to_string(&package).unwrap()))
-        .await
-        .unwrap();
+    let frame = Message::text(serde_json::to_string(&package).unwrap());
+
+    // Only queued, the writer task does the sending
+    match shared_client.gateway_writer.read().unwrap().as_ref() {
+        Some(writer) if matches!(op_code, OpCode::Heartbeat) => writer.send_heartbeat(frame),
+        Some(writer) => writer.send(frame),
+        None => warn!("Not connected, dropped {:?}", op_code),
+    }
 }
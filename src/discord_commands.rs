@@ -1,32 +1,96 @@
 use std::{
-    error::Error,
     ops::Range,
     time::{Duration, Instant},
 };
 
 use futures::future::BoxFuture;
-use log::debug;
+use log::{debug, info, warn};
 use regex::Regex;
 
 use crate::{
-    discord_message::DiscordMessage,
-    model::{MasterCommand, MasterCommandType},
+    custom_error::BotError,
+    discord_client::get_latency,
+    discord_message::{DiscordMessage, SharedDiscordClient},
+    model::{EventKind, GatewayEvent, MasterCommand, MasterCommandType},
 };
 
+/// How many of the last messages of a channel `clean` looks at
+const DEFAULT_CLEAN_LIMIT: usize = 100;
+
+/// Reaction the master confirms with
+const CONFIRM_EMOJI: &str = "✅";
+
+/// How long the master gets to confirm
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub type CommandFuture<'a> = BoxFuture<'a, Result<(), BotError>>;
+
 #[derive(Clone)]
 pub struct Command {
     // When command is None it will only be used as a responder and not a command
     pub command: Option<String>,
+    /// Event the matcher gets to see, MessageCreate or MessageUpdate
+    pub event: EventKind,
     pub cooldown: Duration,
     pub last_called: Option<Instant>,
     pub matcher: fn(&DiscordMessage) -> bool,
-    pub execute: for<'a> fn(&'a DiscordMessage) -> BoxFuture<'a, Result<(), Box<dyn Error>>>,
+    pub execute: for<'a> fn(&'a DiscordMessage) -> CommandFuture<'a>,
+}
+
+/// Handles gateway events directly, for anything that is not covered by a `Command`
+/// (deleted messages, reactions, interaction results, ...)
+#[derive(Clone)]
+pub struct EventHandler {
+    pub event: EventKind,
+    pub matcher: fn(&GatewayEvent) -> bool,
+    pub execute: for<'a> fn(&'a GatewayEvent, &'a SharedDiscordClient) -> CommandFuture<'a>,
+}
+
+/// Commands and event handlers of the client, fixed once it is created
+pub struct CommandRegistry {
+    pub commands: Vec<Command>,
+    pub event_handlers: Vec<EventHandler>,
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        CommandRegistry {
+            commands: get_commands(),
+            event_handlers: get_event_handlers(),
+        }
+    }
+}
+
+/// Asks the author of the message a question, they confirm by reacting
+///
+/// Any other reaction of the author says no, so does not reacting in time. The question is
+/// deleted again unless it was confirmed.
+async fn confirm(message: &DiscordMessage, question: &str) -> Result<bool, BotError> {
+    let confirmation = message.reply(question).await?;
+    confirmation.react(CONFIRM_EMOJI).await?;
+    let deadline = Instant::now() + CONFIRM_TIMEOUT;
+    let confirmed = loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match confirmation.await_reaction(timeout).await {
+            Ok(reaction) if reaction.user_id == message.data.author.id => {
+                break reaction.emoji.is(CONFIRM_EMOJI);
+            }
+            Ok(_) => {}
+            Err(BotError::InteractionTimeout) => break false,
+            Err(error) => return Err(error),
+        }
+    };
+    if !confirmed {
+        confirmation.delete().await?;
+    }
+    Ok(confirmed)
 }
 
 pub fn get_commands() -> Vec<Command> {
     vec![
         // Auto Accept Incoming Trades From Master
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::default(),
@@ -38,7 +102,7 @@ pub fn get_commands() -> Vec<Command> {
                         .data
                         .content
                         .starts_with(&format!("<@{}>", message.user.id))
-                    && message.replied_to(&message.master_id.as_ref().unwrap(), "pls trade")
+                    && message.replied_to(message.master_id.as_ref().unwrap(), "pls trade")
             },
             execute: |message| {
                 Box::pin(async {
@@ -49,6 +113,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Auto Accept Trades From Myself
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::default(),
@@ -70,6 +135,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Master Controls
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::default(),
@@ -91,9 +157,6 @@ pub fn get_commands() -> Vec<Command> {
                             "start" => {
                                 message
                                     .client
-                                    .clone()
-                                    .lock()
-                                    .await
                                     .master_command_sender
                                     .send(MasterCommand {
                                         command: MasterCommandType::Start,
@@ -104,9 +167,6 @@ pub fn get_commands() -> Vec<Command> {
                             "stop" => {
                                 message
                                     .client
-                                    .clone()
-                                    .lock()
-                                    .await
                                     .master_command_sender
                                     .send(MasterCommand {
                                         command: MasterCommandType::Stop,
@@ -116,6 +176,48 @@ pub fn get_commands() -> Vec<Command> {
                             }
                             "say" => {
                                 message.send(&other.join(" ")).await?;
+                                // Only the said text stays, the command can go when it is ours
+                                if message.is_from_me() {
+                                    message.delete().await?;
+                                }
+                            }
+                            "clean" => {
+                                let limit = match other.first() {
+                                    Some(limit) => limit.parse().map_err(|_| {
+                                        BotError::MatcherParse(format!("{} is not a number", limit))
+                                    })?,
+                                    None => DEFAULT_CLEAN_LIMIT,
+                                };
+                                // The bot can not confirm to itself, its own reactions do not count
+                                if !message.is_from_me() {
+                                    let question = format!(
+                                        "Delete my messages among the last {}? React with {}",
+                                        limit, CONFIRM_EMOJI
+                                    );
+                                    if !confirm(message, &question).await? {
+                                        info!("Clean up was not confirmed");
+                                        return Ok(());
+                                    }
+                                }
+                                let deleted = message.delete_own_messages(limit).await?;
+                                info!(
+                                    "Deleted {} messages in {}",
+                                    deleted, message.data.channel_id
+                                );
+                            }
+                            "ping" => {
+                                let latency = get_latency(&message.client);
+                                match latency {
+                                    Some(latency) => {
+                                        message
+                                            .reply(&format!(
+                                                "Pong! Gateway latency is {}ms",
+                                                latency.as_millis()
+                                            ))
+                                            .await?
+                                    }
+                                    None => message.reply("Pong! No heartbeat yet").await?,
+                                };
                             }
                             _ => {
                                 message.reply(":pleading_face:").await?;
@@ -128,6 +230,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // High Low
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls hl")),
             cooldown: Duration::from_secs(30),
@@ -141,10 +244,12 @@ pub fn get_commands() -> Vec<Command> {
                     let description = message.data.embeds[0]
                         .description
                         .as_ref()
-                        .ok_or("No description")?;
+                        .ok_or_else(|| BotError::MatcherParse("no description".to_string()))?;
                     let number_regex = Regex::new(r"\*\*(\d+)\*\*").unwrap();
-                    let number_string = number_regex.captures(description).unwrap();
-                    let number: u8 = number_string[1].parse().expect("not a number");
+                    let number: u8 = number_regex
+                        .captures(description)
+                        .and_then(|captures| captures[1].parse().ok())
+                        .ok_or_else(|| BotError::MatcherParse("no High Low number".to_string()))?;
                     message
                         .click_button(0, if number <= 50 { 2 } else { 0 })
                         .await?;
@@ -152,8 +257,34 @@ pub fn get_commands() -> Vec<Command> {
                 })
             },
         },
+        // High Low Result (Pepe edits the game when it is over)
+        Command {
+            event: EventKind::MessageUpdate,
+            last_called: None,
+            command: None,
+            cooldown: Duration::default(),
+            matcher: |message| {
+                message.is_from_pepe()
+                    && message.replied_to_me("pls hl")
+                    && message.embed_author_contains("high-low")
+                    && (message.embed_description_contains("You won")
+                        || message.embed_description_contains("You lost"))
+            },
+            execute: |message| {
+                Box::pin(async {
+                    let description = message.data.embeds[0]
+                        .description
+                        .as_ref()
+                        .ok_or_else(|| BotError::MatcherParse("no description".to_string()))?;
+                    let result = description.lines().next().unwrap_or_default();
+                    info!("High Low: {}", result.replace("**", ""));
+                    Ok(())
+                })
+            },
+        },
         // Hunting
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls hunt")),
             cooldown: Duration::from_secs(40),
@@ -182,6 +313,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Fishing
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls fish")),
             cooldown: Duration::from_secs(40),
@@ -212,6 +344,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Digging
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls dig")),
             cooldown: Duration::from_secs(40),
@@ -220,6 +353,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Begging
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls beg")),
             cooldown: Duration::from_secs(45),
@@ -228,6 +362,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Deposit All
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls dep all")),
             cooldown: Duration::from_secs(60),
@@ -236,6 +371,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Trivia
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls trivia")),
             cooldown: Duration::from_secs(5),
@@ -253,6 +389,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Post Memes
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls pm")),
             cooldown: Duration::from_secs(30),
@@ -274,6 +411,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Stream
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls stream")),
             cooldown: Duration::from_secs(60 * 10),
@@ -287,61 +425,55 @@ pub fn get_commands() -> Vec<Command> {
             execute: |message| {
                 Box::pin(async {
                     let button = message.get_component(0, 0);
-                    match button {
-                        Some(button) => {
-                            match button.label.unwrap().as_str() {
-                                "Go Live" => {
-                                    // Start Stream
-                                    if !button.disabled {
-                                        // click start
-                                        message.click_button(0, 0).await?;
-                                        // await update
-                                        let updated_message = message.await_update().await?;
-                                        // choose game
-                                        let game_row = updated_message.get_component(0, 0).unwrap();
-                                        updated_message
-                                            .select_option(
-                                                0,
-                                                random_range(0..game_row.options.len()),
-                                            )
-                                            .await?;
-                                        // await update
-                                        let updated_message_two = message.await_update().await?;
-                                        // click start
-                                        updated_message_two.click_button(1, 0).await?;
-                                        // await update
-                                        let updated_message_three =
-                                            updated_message_two.await_update().await?;
-                                        // click one of the stream buttons
-                                        updated_message_three
-                                            .click_button(0, random_range(0..3))
-                                            .await
-                                            .ok();
-
-                                        let updated = updated_message_three.await_update().await?;
-
-                                        // end interaction
-                                        if updated.get_component(1, 1).is_some() {
-                                            updated_message_three.click_button(1, 1).await?;
-                                        } else {
-                                            updated_message_three.click_button(0, 2).await?;
-                                        }
+                    if let Some(button) = button {
+                        match button.label.unwrap().as_str() {
+                            "Go Live" => {
+                                // Start Stream
+                                if !button.disabled {
+                                    // click start
+                                    message.click_button(0, 0).await?;
+                                    // await update
+                                    let updated_message = message.await_update().await?;
+                                    // choose game
+                                    let game_row = updated_message.get_component(0, 0).unwrap();
+                                    updated_message
+                                        .select_option(0, random_range(0..game_row.options.len()))
+                                        .await?;
+                                    // await update
+                                    let updated_message_two = message.await_update().await?;
+                                    // click start
+                                    updated_message_two.click_button(1, 0).await?;
+                                    // await update
+                                    let updated_message_three =
+                                        updated_message_two.await_update().await?;
+                                    // click one of the stream buttons
+                                    updated_message_three
+                                        .click_button(0, random_range(0..3))
+                                        .await
+                                        .ok();
+
+                                    let updated = updated_message_three.await_update().await?;
+
+                                    // end interaction
+                                    if updated.get_component(1, 1).is_some() {
+                                        updated_message_three.click_button(1, 1).await?;
                                     } else {
-                                        // can't stream
-                                        message.click_button(0, 2).await?;
+                                        updated_message_three.click_button(0, 2).await?;
                                     }
+                                } else {
+                                    // can't stream
+                                    message.click_button(0, 2).await?;
                                 }
-                                "Run AD" => {
-                                    // Is Streaming
-                                    if !button.disabled {
-                                        message.click_button(0, random_range(0..3)).await?;
-                                    }
-                                    message.click_button(1, 1).await?;
+                            }
+                            "Run AD" => {
+                                // Is Streaming
+                                if !button.disabled {
+                                    message.click_button(0, random_range(0..3)).await?;
                                 }
-                                _ => {}
+                                message.click_button(1, 1).await?;
                             }
+                            _ => {}
                         }
-                        None => {}
                     }
                     Ok(())
                 })
@@ -349,6 +481,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Work
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls work")),
             cooldown: Duration::from_secs(3600),
@@ -357,6 +490,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Pet
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls pet")),
             cooldown: Duration::from_secs(60 * 20),
@@ -364,8 +498,7 @@ pub fn get_commands() -> Vec<Command> {
                 let button = message.get_component(0, 0);
                 message.is_from_pepe()
                     && message.embed_title_contains(message.user.username.as_str())
-                    && button.is_some()
-                    && button.unwrap().label.as_ref().unwrap() == "Feed"
+                    && button.and_then(|button| button.label).as_deref() == Some("Feed")
             },
             execute: |message| {
                 Box::pin(async {
@@ -380,6 +513,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         // Daily
         Command {
+            event: EventKind::MessageCreate,
             last_called: None,
             command: Some(String::from("pls daily")),
             cooldown: Duration::from_secs(3600 * 24),
@@ -389,6 +523,7 @@ pub fn get_commands() -> Vec<Command> {
         // ## Mini Games ##
         Command {
             // Repeat Words Order
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::from_secs(0),
@@ -424,6 +559,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         Command {
             // Emoji Match
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::from_secs(0),
@@ -456,6 +592,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         Command {
             // Soccer
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::from_secs(0),
@@ -482,6 +619,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         Command {
             // Color Match
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::from_secs(0),
@@ -494,10 +632,10 @@ pub fn get_commands() -> Vec<Command> {
                     let lines = message.data.content.split("\n").collect::<Vec<&str>>();
                     let matches = &mut lines[1..4].iter().map(|line| {
                         let (color, word) = line.split_once(" ").unwrap();
-                        return ColorMatch {
+                        ColorMatch {
                             color: color.to_lowercase().chars().nth(2).unwrap(),
                             word: word[1..word.len() - 1].to_owned(),
-                        };
+                        }
                     });
 
                     debug!(
@@ -528,7 +666,9 @@ pub fn get_commands() -> Vec<Command> {
                         let buttons = &message.data.components[0].components;
                         let color_match = matches
                             .find(|color_match| color_match.word == word)
-                            .ok_or("could not find color [1]")?;
+                            .ok_or_else(|| {
+                                BotError::MatcherParse(format!("no color for {}", word))
+                            })?;
                         let index = buttons
                             .iter()
                             .position(|button| {
@@ -539,7 +679,12 @@ pub fn get_commands() -> Vec<Command> {
                                     .to_lowercase()
                                     .starts_with(color_match.color)
                             })
-                            .ok_or("could not find color [2]")?;
+                            .ok_or_else(|| {
+                                BotError::MatcherParse(format!(
+                                    "no button for {}",
+                                    color_match.color
+                                ))
+                            })?;
 
                         debug!("Color Match index is [4], {}", &index);
 
@@ -552,6 +697,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         Command {
             // Dunk the ball
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::from_secs(0),
@@ -586,6 +732,7 @@ pub fn get_commands() -> Vec<Command> {
         // Events
         Command {
             // Attack the Boss
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::from_secs(0),
@@ -609,6 +756,7 @@ pub fn get_commands() -> Vec<Command> {
         },
         Command {
             // Trivia Night
+            event: EventKind::MessageCreate,
             last_called: None,
             command: None,
             cooldown: Duration::from_secs(0),
@@ -628,6 +776,40 @@ pub fn get_commands() -> Vec<Command> {
     ]
 }
 
+pub fn get_event_handlers() -> Vec<EventHandler> {
+    vec![
+        // Discord rejected a click or select
+        EventHandler {
+            event: EventKind::InteractionFailure,
+            matcher: |_event| true,
+            execute: |event, _client| {
+                Box::pin(async move {
+                    if let GatewayEvent::InteractionFailure(data) = event {
+                        warn!("Discord rejected interaction {}", data.id);
+                    }
+                    Ok(())
+                })
+            },
+        },
+        // Someone else is using the account at the same time
+        EventHandler {
+            event: EventKind::SessionsReplace,
+            matcher: |event| matches!(event, GatewayEvent::SessionsReplace(sessions) if sessions.len() > 1),
+            execute: |event, _client| {
+                Box::pin(async move {
+                    if let GatewayEvent::SessionsReplace(sessions) = event {
+                        warn!(
+                            "The account is logged in {} times, Dank Memer may notice",
+                            sessions.len()
+                        );
+                    }
+                    Ok(())
+                })
+            },
+        },
+    ]
+}
+
 fn random_range(range: Range<usize>) -> usize {
     (rand::random::<f32>() * range.end as f32).floor() as usize + range.start
 }
@@ -1,87 +1,233 @@
-use futures::lock::Mutex;
-use std::{sync::Arc, time::Duration};
-use tokio::time::timeout;
+use log::warn;
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicU64, Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{broadcast, Notify},
+    task::JoinHandle,
+    time::timeout_at,
+};
 
-use reqwest::Client;
+use reqwest::{Client, Method};
 
-use crate::{custom_error::MyError, model::*};
+use crate::{
+    custom_error::BotError,
+    discord_commands::CommandRegistry,
+    gateway_writer::GatewayWriter,
+    model::*,
+    recorder::Recorder,
+    rest::{encode_path_segment, post, request, RateLimiter},
+};
 
 const PEPE_ID: &str = "270904126974590976";
 
+/// How many updates (and reactions) of one message are kept while it is not awaiting them
+const MESSAGE_UPDATE_CAPACITY: usize = 16;
+
+/// How long to wait for a message to be updated
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most messages Discord returns for one request
+const MESSAGES_PAGE_SIZE: usize = 100;
+
+/// Shared by the connection, the handlers and every message
+///
+/// The configuration is fixed once the client is created, everything else has a lock of its own,
+/// so a handler clicking a button never waits for the connection or other handlers.
 pub struct DiscordClient {
     pub token: String,
-    pub user: Option<ReadyDataUser>,
-    pub sequence: u64,
-    pub session_id: String,
     pub master_id: Option<String>,
     pub http: Client,
-    pub message_update_receiver: async_channel::Receiver<DiscordMessage>,
-    pub websocket_writer: futures::stream::SplitSink<
-        tokio_tungstenite::WebSocketStream<
-            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-        >,
-        tokio_tungstenite::tungstenite::Message,
-    >,
+    /// Base url of the Discord REST API, without a trailing slash
+    pub api_url: String,
+    /// Records the traffic when set
+    pub(crate) recorder: Option<Arc<Recorder>>,
+    pub rest_mode: RestMode,
+    pub(crate) rate_limits: RateLimiter,
+    /// MESSAGE_UPDATEs go only to the messages with that id
+    pub(crate) message_updates: MessageSubscriptions<MessageCreateData>,
+    /// MESSAGE_REACTION_ADDs go only to the messages they were added to
+    pub(crate) message_reactions: MessageSubscriptions<ReactionEventData>,
     pub master_command_sender: async_channel::Sender<MasterCommand>,
-    pub commands: Vec<crate::discord_commands::Command>,
+    pub registry: CommandRegistry,
+    /// Sequence of the last dispatch, 0 without a session
+    pub sequence: AtomicU64,
+    /// Empty without a session
+    pub session_id: RwLock<String>,
+    /// Gateway the session has to be resumed on, set by READY
+    pub resume_gateway_url: RwLock<Option<String>>,
+    /// Set by the first READY
+    pub user: RwLock<Option<ReadyDataUser>>,
+    /// Queues frames for the gateway connection, set while connected
+    pub(crate) gateway_writer: RwLock<Option<GatewayWriter>>,
+    pub connection: Mutex<Connection>,
+    /// When each farming command was last sent
+    pub cooldowns: Mutex<HashMap<String, Instant>>,
+}
+
+/// State of the current gateway connection
+pub struct Connection {
+    pub state: ConnectionState,
+    /// Whether the last heartbeat got acknowledged by Discord
+    pub heartbeat_acked: bool,
+    pub last_heartbeat: Option<Instant>,
+    /// Round-trip time of the last acknowledged heartbeat
+    pub latency: Option<Duration>,
+    /// Notified to drop the current connection and reconnect
+    pub disconnect: Arc<Notify>,
+    /// Heartbeat task of the current connection
+    pub heartbeat: Option<JoinHandle<()>>,
+    /// Task sending the farming commands, only runs while connected
+    pub farming: Option<JoinHandle<()>>,
+    /// Set once shutdown started, new messages no longer run commands
+    pub shutting_down: bool,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Connection {
+            state: ConnectionState::Disconnected,
+            heartbeat_acked: true,
+            last_heartbeat: None,
+            latency: None,
+            disconnect: Arc::new(Notify::new()),
+            heartbeat: None,
+            farming: None,
+            shutting_down: false,
+        }
+    }
+}
+
+/// A channel for every message that may still await something, by message id
+///
+/// Messages only get their own events, so a busy channel can not push them out.
+pub(crate) struct MessageSubscriptions<T> {
+    senders: Mutex<HashMap<String, broadcast::Sender<T>>>,
+}
+
+impl<T: Clone> Default for MessageSubscriptions<T> {
+    fn default() -> Self {
+        MessageSubscriptions {
+            senders: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> MessageSubscriptions<T> {
+    pub(crate) fn subscribe(&self, message_id: &str) -> broadcast::Receiver<T> {
+        let mut senders = self.senders.lock().unwrap();
+        // Messages that were dropped do not need their channel anymore
+        senders.retain(|_, sender| sender.receiver_count() > 0);
+        senders
+            .entry(message_id.to_string())
+            .or_insert_with(|| broadcast::channel(MESSAGE_UPDATE_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Nobody waiting for the message is fine
+    pub(crate) fn send(&self, message_id: &str, event: T) {
+        if let Some(sender) = self.senders.lock().unwrap().get(message_id) {
+            sender.send(event).ok();
+        }
+    }
+}
+
+impl DiscordClient {
+    pub fn session_id(&self) -> String {
+        self.session_id.read().unwrap().to_string()
+    }
+
+    pub fn user(&self) -> Option<ReadyDataUser> {
+        self.user.read().unwrap().clone()
+    }
+
+    pub fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap()
+    }
 }
 
-pub type SharedDiscordClient = Arc<Mutex<DiscordClient>>;
+pub type SharedDiscordClient = Arc<DiscordClient>;
+
+pub type WebSocketStream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+pub type WebSocketWriter =
+    futures::stream::SplitSink<WebSocketStream, tokio_tungstenite::tungstenite::Message>;
 
 pub struct DiscordMessage {
     pub master_id: Option<String>,
     pub user: ReadyDataUser,
     pub data: MessageCreateData,
     pub client: SharedDiscordClient,
+    /// Subscribed when the message is created, so no update after that is missed
+    updates: futures::lock::Mutex<broadcast::Receiver<MessageCreateData>>,
+    /// Subscribed when the message is created, like the updates
+    reactions: futures::lock::Mutex<broadcast::Receiver<ReactionEventData>>,
 }
 
 impl DiscordMessage {
-    pub async fn new(data: MessageCreateData, client: SharedDiscordClient) -> DiscordMessage {
-        let client_arc = client.clone();
-        let client_mutex = client_arc.lock().await;
-        DiscordMessage {
-            master_id: client_mutex.master_id.clone(),
-            user: client_mutex.user.as_ref().unwrap().clone(),
+    /// None until the client knows its user from a READY
+    pub fn new(data: MessageCreateData, client: SharedDiscordClient) -> Option<DiscordMessage> {
+        let user = client.user()?;
+        Some(DiscordMessage {
+            master_id: client.master_id.clone(),
+            user,
+            updates: futures::lock::Mutex::new(client.message_updates.subscribe(&data.id)),
+            reactions: futures::lock::Mutex::new(client.message_reactions.subscribe(&data.id)),
             data,
             client,
-        }
+        })
     }
 
     pub fn new_from(&self, data: MessageCreateData) -> DiscordMessage {
         DiscordMessage {
-            data,
             master_id: self.master_id.clone(),
             user: self.user.clone(),
             client: self.client.clone(),
+            updates: futures::lock::Mutex::new(self.client.message_updates.subscribe(&data.id)),
+            reactions: futures::lock::Mutex::new(self.client.message_reactions.subscribe(&data.id)),
+            data,
+        }
+    }
+
+    /// Reads the message Discord answered with
+    async fn message_from(&self, response: reqwest::Response) -> Result<DiscordMessage, BotError> {
+        let text = response.text().await?;
+        match serde_json::from_str(&text) {
+            Ok(data) => Ok(self.new_from(data)),
+            Err(error) => Err(BotError::deserialize("message", &text, error)),
         }
     }
 
     pub fn replied_to_me(&self, starts_with: &str) -> bool {
-        return self.replied_to(&self.user.id, starts_with);
+        self.replied_to(&self.user.id, starts_with)
     }
 
     pub fn replied_to(&self, user_id: &str, starts_with: &str) -> bool {
         match &self.data.referenced_message {
-            Some(ref_msg) => ref_msg.author.id == user_id && ref_msg.content.starts_with(starts_with),
+            Some(ref_msg) => {
+                ref_msg.author.id == user_id && ref_msg.content.starts_with(starts_with)
+            }
             None => false,
         }
     }
 
     pub fn is_from(&self, user_id: &str) -> bool {
-        return self.data.author.id == user_id;
+        self.data.author.id == user_id
     }
 
     pub fn is_from_master(&self) -> bool {
-        return self.master_id.is_some()
-            && &self.data.author.id == self.master_id.as_ref().unwrap();
+        self.master_id.is_some() && &self.data.author.id == self.master_id.as_ref().unwrap()
     }
 
     pub fn is_from_pepe(&self) -> bool {
-        return self.is_from(PEPE_ID);
+        self.is_from(PEPE_ID)
     }
 
     pub fn is_from_me(&self) -> bool {
-        return self.is_from(&self.user.id);
+        self.is_from(&self.user.id)
     }
 
     pub fn get_component(&self, row: usize, column: usize) -> Option<MessageComponent> {
@@ -96,18 +242,18 @@ impl DiscordMessage {
     }
 
     pub fn embed_title_contains(&self, content: &str) -> bool {
-        return !self.data.embeds.is_empty()
+        !self.data.embeds.is_empty()
             && self.data.embeds[0].title.is_some()
             && self.data.embeds[0]
                 .title
                 .as_ref()
                 .unwrap()
                 .to_lowercase()
-                .contains(&content.to_lowercase());
+                .contains(&content.to_lowercase())
     }
 
     pub fn embed_author_contains(&self, content: &str) -> bool {
-        return !self.data.embeds.is_empty()
+        !self.data.embeds.is_empty()
             && self.data.embeds[0].author.is_some()
             && self.data.embeds[0]
                 .author
@@ -115,38 +261,39 @@ impl DiscordMessage {
                 .unwrap()
                 .name
                 .to_lowercase()
-                .contains(&content.to_lowercase());
+                .contains(&content.to_lowercase())
     }
 
     pub fn embed_description_contains(&self, content: &str) -> bool {
-        return !self.data.embeds.is_empty()
+        !self.data.embeds.is_empty()
             && self.data.embeds[0].description.is_some()
             && self.data.embeds[0]
                 .description
                 .as_ref()
                 .unwrap()
                 .to_lowercase()
-                .contains(&content.to_lowercase());
+                .contains(&content.to_lowercase())
     }
 
-    pub async fn click_button(
-        &self,
-        row: usize,
-        column: usize,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        if row >= self.data.components.len() {
-            return Err(Box::new(MyError::new("Component Row out of bounds")));
-        }
-        let row = &self.data.components[row];
-        if column >= row.components.len() {
-            return Err(Box::new(MyError::new("Component Button out of bounds")));
-        }
-        let button = &row.components[column];
-        if button.component_type == ComponentType::Button && button.disabled == false {
-            let client_c = self.client.clone();
-            let client = client_c.lock().await;
+    pub async fn click_button(&self, row: usize, column: usize) -> Result<(), BotError> {
+        let button = self
+            .data
+            .components
+            .get(row)
+            .and_then(|components| components.components.get(column));
+        let button = match button {
+            Some(button) => button,
+            None => {
+                return Err(BotError::ComponentNotFound(format!(
+                    "Button {} in row {}",
+                    column, row
+                )))
+            }
+        };
+        if button.component_type == ComponentType::Button && !button.disabled {
+            let session_id = self.client.session_id();
             let body = serde_json::to_string(&DiscordMessageInteraction {
-                session_id: client.session_id.to_string(),
+                session_id,
                 application_id: self.data.author.id.to_string(),
                 channel_id: self.data.channel_id.to_string(),
                 discord_message_interaction_type: 3,
@@ -158,43 +305,43 @@ impl DiscordMessage {
                     type_type: None,
                     values: None,
                 },
-            })?;
-            let http = client.http.clone();
-            drop(client);
-
-            http.post("https://discord.com/api/v9/interactions")
-                .body(body)
-                .send()
-                .await?;
+            })
+            .unwrap();
+
+            post(&self.client, "/interactions", body).await?;
         }
         tokio::time::sleep(Duration::from_millis(200)).await;
         Ok(())
     }
 
-    pub async fn select_option(
-        &self,
-        row: usize,
-        option: usize,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        if row >= self.data.components.len() {
-            return Err(Box::new(MyError::new("Component Row out of bounds")));
-        }
-        let row = &self.data.components[row];
-        if row.components.is_empty() {
-            return Err(Box::new(MyError::new("No Select Menu in Row")));
-        }
-        let select_menu = &row.components[0];
-        if option >= select_menu.options.len() {
-            return Err(Box::new(MyError::new("Option out of bounds")));
-        }
-        let option = &select_menu.options[option];
-        if select_menu.component_type == ComponentType::SelectMenu && select_menu.disabled == false
+    pub async fn select_option(&self, row: usize, option: usize) -> Result<(), BotError> {
+        let select_menu = match self
+            .data
+            .components
+            .get(row)
+            .and_then(|components| components.components.first())
         {
-            let client_c = self.client.clone();
-            let client = client_c.lock().await;
-
+            Some(select_menu) => select_menu,
+            None => {
+                return Err(BotError::ComponentNotFound(format!(
+                    "Select menu in row {}",
+                    row
+                )))
+            }
+        };
+        let option = match select_menu.options.get(option) {
+            Some(option) => option,
+            None => {
+                return Err(BotError::ComponentNotFound(format!(
+                    "Option {} of the select menu in row {}",
+                    option, row
+                )))
+            }
+        };
+        if select_menu.component_type == ComponentType::SelectMenu && !select_menu.disabled {
+            let session_id = self.client.session_id();
             let body = serde_json::to_string(&DiscordMessageInteraction {
-                session_id: client.session_id.to_string(),
+                session_id,
                 application_id: self.data.author.id.to_string(),
                 channel_id: self.data.channel_id.to_string(),
                 discord_message_interaction_type: 3,
@@ -206,111 +353,214 @@ impl DiscordMessage {
                     type_type: Some(3),
                     values: Some(vec![option.value.to_string()]),
                 },
-            })?;
-            let http = client.http.clone();
-            drop(client);
-
-            http.post("https://discord.com/api/v9/interactions")
-                .body(body)
-                .send()
-                .await?;
+            })
+            .unwrap();
+
+            post(&self.client, "/interactions", body).await?;
         }
         tokio::time::sleep(Duration::from_millis(200)).await;
         Ok(())
     }
 
-    pub async fn reply(&self, content: &str) -> Result<DiscordMessage, Box<dyn std::error::Error>> {
-        let response = self
-            .client
-            .clone()
-            .lock()
-            .await
-            .http
-            .post(format!(
-                "https://discord.com/api/v9/channels/{}/messages",
-                self.data.channel_id
-            ))
-            .body(
-                serde_json::to_string(&DiscordMessagePayload {
-                    content: content.to_string(),
-                    message_reference: Some(DiscordMessagePayloadReference {
-                        channel_id: self.data.channel_id.to_string(),
-                        guild_id: self.data.guild_id.as_ref().unwrap().to_string(),
-                        message_id: self.data.id.to_string(),
-                    }),
-                })
-                .unwrap(),
-            )
-            .send()
-            .await?;
-
-        return Ok(self.new_from(serde_json::from_str(&response.text().await?)?));
-    }
-
-    pub async fn send(&self, content: &str) -> Result<DiscordMessage, Box<dyn std::error::Error>> {
-        let response = self
-            .client
-            .clone()
-            .lock()
-            .await
-            .http
-            .post(format!(
-                "https://discord.com/api/v9/channels/{}/messages",
-                self.data.channel_id
-            ))
-            .body(
-                serde_json::to_string(&DiscordMessagePayload {
-                    content: content.to_string(),
-                    message_reference: None,
-                })
-                .unwrap(),
-            )
-            .send()
-            .await?;
-        return Ok(self.new_from(serde_json::from_str(&response.text().await?)?));
-    }
-
-    // pub async fn edit(&self, content: &str) -> Result<DiscordMessage, Box<dyn std::error::Error>> {
-    //     if self.data.author.id != self.client.clone().lock().await.user.as_ref().unwrap().id {
-    //         panic!("Tried to edit a message that is not yours");
-    //     }
-
-    //     let response = self
-    //         .client
-    //         .clone()
-    //         .lock()
-    //         .await
-    //         .http
-    //         .patch(format!(
-    //             "https://discord.com/api/v9/channels/{}/messages/{}",
-    //             self.data.channel_id, self.data.id
-    //         ))
-    //         .body(
-    //             serde_json::to_string(&DiscordMessagePayload {
-    //                 content: content.to_string(),
-    //                 message_reference: None,
-    //             })
-    //             .unwrap(),
-    //         )
-    //         .send()
-    //         .await?;
-
-    //     return Ok(self.new_from(serde_json::from_str(&response.text().await?)?));
-    // }
-
-    pub async fn await_update(&self) -> Result<DiscordMessage, Box<dyn std::error::Error>> {
-        let receiver = self
-            .client
-            .clone()
-            .lock()
-            .await
-            .message_update_receiver
-            .clone();
+    pub async fn reply(&self, content: &str) -> Result<DiscordMessage, BotError> {
+        let body = serde_json::to_string(&DiscordMessagePayload {
+            content: content.to_string(),
+            message_reference: Some(DiscordMessagePayloadReference {
+                channel_id: self.data.channel_id.to_string(),
+                guild_id: self.data.guild_id.clone(),
+                message_id: self.data.id.to_string(),
+            }),
+        })
+        .unwrap();
+        let response = post(
+            &self.client,
+            &format!("/channels/{}/messages", self.data.channel_id),
+            body,
+        )
+        .await?;
+
+        self.message_from(response).await
+    }
+
+    pub async fn send(&self, content: &str) -> Result<DiscordMessage, BotError> {
+        let body = serde_json::to_string(&DiscordMessagePayload {
+            content: content.to_string(),
+            message_reference: None,
+        })
+        .unwrap();
+        let response = post(
+            &self.client,
+            &format!("/channels/{}/messages", self.data.channel_id),
+            body,
+        )
+        .await?;
+        self.message_from(response).await
+    }
+
+    /// Changes the content of this message, Discord only allows that for messages of this account
+    pub async fn edit(&self, content: &str) -> Result<DiscordMessage, BotError> {
+        let body = serde_json::to_string(&EditMessagePayload {
+            content: content.to_string(),
+        })
+        .unwrap();
+        let response = request(
+            &self.client,
+            Method::PATCH,
+            &format!(
+                "/channels/{}/messages/{}",
+                self.data.channel_id, self.data.id
+            ),
+            Some(body),
+        )
+        .await?;
+        self.message_from(response).await
+    }
+
+    /// Deletes this message, messages of others need the Manage Messages permission
+    pub async fn delete(&self) -> Result<(), BotError> {
+        delete_message(&self.client, &self.data.channel_id, &self.data.id).await
+    }
+
+    /// Deletes the messages of this account among the last `limit` messages of the channel
+    ///
+    /// User accounts can not bulk delete, so they go one by one at the pace of the rate limit.
+    /// Returns how many were deleted.
+    pub async fn delete_own_messages(&self, limit: usize) -> Result<usize, BotError> {
+        let mut before: Option<String> = None;
+        let mut checked = 0;
+        let mut deleted = 0;
+        while checked < limit {
+            let page_size = (limit - checked).min(MESSAGES_PAGE_SIZE);
+            let mut path = format!(
+                "/channels/{}/messages?limit={}",
+                self.data.channel_id, page_size
+            );
+            if let Some(before) = &before {
+                path.push_str(&format!("&before={}", before));
+            }
+            let response = request(&self.client, Method::GET, &path, None).await?;
+            let text = response.text().await?;
+            let messages: Vec<MessageCreateData> = serde_json::from_str(&text)
+                .map_err(|error| BotError::deserialize("messages", &text, error))?;
+            // A short page is the start of the channel
+            let last_page = messages.len() < page_size;
+            checked += messages.len();
+            // Newest first, so the next page starts after the last one
+            before = messages.last().map(|message| message.id.clone());
+            for message in messages {
+                if message.author.id == self.user.id {
+                    delete_message(&self.client, &message.channel_id, &message.id).await?;
+                    deleted += 1;
+                }
+            }
+            if last_page {
+                break;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Adds a reaction of this account, the emoji is the unicode emoji or `name:id` for custom emojis
+    pub async fn react(&self, emoji: &str) -> Result<(), BotError> {
+        request(
+            &self.client,
+            Method::PUT,
+            &self.own_reaction_path(emoji),
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Removes the reaction of this account, see `react`
+    pub async fn unreact(&self, emoji: &str) -> Result<(), BotError> {
+        request(
+            &self.client,
+            Method::DELETE,
+            &self.own_reaction_path(emoji),
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn own_reaction_path(&self, emoji: &str) -> String {
+        format!(
+            "/channels/{}/messages/{}/reactions/{}/@me",
+            self.data.channel_id,
+            self.data.id,
+            encode_path_segment(emoji)
+        )
+    }
+
+    /// Reactions of the emoji the message had when it was received
+    pub fn reaction(&self, emoji: &str) -> Option<&Reaction> {
+        self.data
+            .reactions
+            .iter()
+            .flatten()
+            .find(|reaction| reaction.emoji.is(emoji))
+    }
+
+    /// Waits for someone to react to this message
+    ///
+    /// Reactions since the message was created (or last awaited) count as well,
+    /// the ones of this account do not, so `react` can offer the choices first.
+    pub async fn await_reaction(&self, timeout: Duration) -> Result<ReactionEventData, BotError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut reactions = self.reactions.lock().await;
         loop {
-            let message = timeout(Duration::from_secs(10), receiver.recv()).await??;
-            if message.data.id == self.data.id {
-                break Ok(message);
+            let reaction = timeout_at(deadline, reactions.recv())
+                .await
+                .map_err(|_| BotError::InteractionTimeout)?;
+            match reaction {
+                Ok(data) if data.user_id != self.user.id => break Ok(data),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Missed {} reactions", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    break Err(BotError::GatewayClosed {
+                        code: None,
+                        reason: "Reactions stopped".to_string(),
+                    });
+                }
             }
         }
     }
+
+    /// Waits for the next update of this message
+    ///
+    /// Updates that came in since the message was created (or last awaited) count as well.
+    pub async fn await_update(&self) -> Result<DiscordMessage, BotError> {
+        let deadline = tokio::time::Instant::now() + UPDATE_TIMEOUT;
+        let mut updates = self.updates.lock().await;
+        loop {
+            let update = timeout_at(deadline, updates.recv())
+                .await
+                .map_err(|_| BotError::InteractionTimeout)?;
+            match update {
+                Ok(data) => break Ok(self.new_from(data)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Missed {} message updates", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    break Err(BotError::GatewayClosed {
+                        code: None,
+                        reason: "Message updates stopped".to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+async fn delete_message(
+    client: &DiscordClient,
+    channel_id: &str,
+    message_id: &str,
+) -> Result<(), BotError> {
+    let path = format!("/channels/{}/messages/{}", channel_id, message_id);
+    request(client, Method::DELETE, &path, None).await?;
+    Ok(())
 }
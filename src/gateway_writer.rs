@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use futures::SinkExt;
+use log::{debug, error, warn};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::discord_message::WebSocketWriter;
+
+/// Frames the gateway accepts per window before closing the connection with 4008
+const SEND_LIMIT: usize = 120;
+/// Window of the send limit
+const SEND_WINDOW: Duration = Duration::from_secs(60);
+/// Part of the send limit only heartbeats may use, so they never wait behind other frames
+const HEARTBEAT_RESERVE: usize = 5;
+
+enum Outbound {
+    Frame(Message),
+    Close(CloseFrame<'static>),
+}
+
+/// Queues frames for the task that owns the sending half of the gateway connection
+///
+/// Sending never waits for the connection, heartbeats skip the queue of the other frames.
+#[derive(Clone)]
+pub struct GatewayWriter {
+    priority: mpsc::UnboundedSender<Outbound>,
+    frames: mpsc::UnboundedSender<Outbound>,
+}
+
+impl GatewayWriter {
+    /// Starts the writer task, it stops after a close frame or once every writer is dropped
+    pub fn spawn(sink: WebSocketWriter) -> (GatewayWriter, JoinHandle<()>) {
+        let (priority, priority_receiver) = mpsc::unbounded_channel();
+        let (frames, frames_receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(write(sink, priority_receiver, frames_receiver));
+        (GatewayWriter { priority, frames }, task)
+    }
+
+    pub fn send(&self, frame: Message) {
+        if self.frames.send(Outbound::Frame(frame)).is_err() {
+            warn!("Gateway writer stopped, dropped a frame");
+        }
+    }
+
+    pub fn send_heartbeat(&self, frame: Message) {
+        if self.priority.send(Outbound::Frame(frame)).is_err() {
+            warn!("Gateway writer stopped, dropped a heartbeat");
+        }
+    }
+
+    /// Sends the close frame ahead of the queued frames, which are dropped
+    pub fn close(&self, frame: CloseFrame<'static>) {
+        self.priority.send(Outbound::Close(frame)).ok();
+    }
+}
+
+async fn write(
+    mut sink: WebSocketWriter,
+    mut priority: mpsc::UnboundedReceiver<Outbound>,
+    mut frames: mpsc::UnboundedReceiver<Outbound>,
+) {
+    let mut limit = SendLimit::default();
+
+    loop {
+        let has_room = limit.has_room(HEARTBEAT_RESERVE);
+        let outbound = tokio::select! {
+            biased;
+            Some(outbound) = priority.recv() => outbound,
+            Some(outbound) = frames.recv(), if has_room => outbound,
+            _ = tokio::time::sleep_until(limit.next_free()), if !has_room => continue,
+            else => break,
+        };
+
+        let frame = match outbound {
+            Outbound::Frame(frame) => frame,
+            Outbound::Close(close_frame) => {
+                sink.send(Message::Close(Some(close_frame))).await.ok();
+                break;
+            }
+        };
+
+        // Heartbeats only wait once the reserve is used up as well
+        if !limit.has_room(0) {
+            debug!("Reached the gateway send limit, waiting");
+            tokio::time::sleep_until(limit.next_free()).await;
+        }
+        limit.sent();
+
+        if let Err(error) = sink.send(frame).await {
+            error!("Failed to send to the gateway: {}", error);
+        }
+    }
+}
+
+/// Sliding window of the frames sent in the last `SEND_WINDOW`
+#[derive(Default)]
+struct SendLimit {
+    sent: VecDeque<Instant>,
+}
+
+impl SendLimit {
+    fn has_room(&mut self, reserve: usize) -> bool {
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| sent.elapsed() >= SEND_WINDOW)
+        {
+            self.sent.pop_front();
+        }
+        self.sent.len() + reserve < SEND_LIMIT
+    }
+
+    /// When the oldest frame leaves the window
+    fn next_free(&self) -> Instant {
+        self.sent
+            .front()
+            .map(|sent| *sent + SEND_WINDOW)
+            .unwrap_or_else(Instant::now)
+    }
+
+    fn sent(&mut self) {
+        self.sent.push_back(Instant::now());
+    }
+}
@@ -0,0 +1,45 @@
+//! Discord self bot that automates the Dank Memer game
+//!
+//! The `pepe-bot` binary is a CLI on top of this crate, other tools can build their own bot:
+//!
+//! ```no_run
+//! use pepe_bot::{model::EventKind, Bot, Command};
+//!
+//! # async fn run() -> Result<(), pepe_bot::custom_error::BotError> {
+//! Bot::builder("token")
+//!     .master_id(Some("123456789".to_string()))
+//!     .command(Command {
+//!         command: None,
+//!         event: EventKind::MessageCreate,
+//!         cooldown: Default::default(),
+//!         last_called: None,
+//!         matcher: |message| message.is_from_master() && message.data.content == "hi",
+//!         execute: |message| {
+//!             Box::pin(async {
+//!                 message.reply("hello").await?;
+//!                 Ok(())
+//!             })
+//!         },
+//!     })
+//!     .build()
+//!     .connect()
+//!     .await
+//! # }
+//! ```
+
+pub mod bot;
+pub mod custom_error;
+pub mod discord_client;
+pub mod discord_commands;
+pub mod discord_message;
+pub(crate) mod gateway_writer;
+pub mod model;
+pub(crate) mod recorder;
+pub mod replay;
+pub(crate) mod rest;
+pub(crate) mod zlib_stream;
+
+pub use bot::{Bot, BotBuilder, ShutdownHandle};
+pub use discord_client::ClientConfig;
+pub use discord_commands::{Command, CommandRegistry, EventHandler};
+pub use discord_message::{DiscordClient, DiscordMessage, SharedDiscordClient};
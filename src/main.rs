@@ -1,13 +1,12 @@
-mod custom_error;
-mod discord_client;
-mod discord_commands;
-mod discord_message;
-mod model;
+use pepe_bot::discord_client::{
+    DEFAULT_API_URL, DEFAULT_GATEWAY_URL, DEFAULT_MAX_RECONNECT_ATTEMPTS,
+};
+use pepe_bot::Bot;
 
-use crate::discord_client::connect;
-
-use clap::Parser;
+use clap::{CommandFactory, ErrorKind, Parser, Subcommand};
 use clap_verbosity_flag::InfoLevel;
+use log::{error, info};
+use std::path::PathBuf;
 
 /// Automate Dank Memer
 #[derive(Parser, Debug)]
@@ -18,7 +17,7 @@ struct Args {
 
     /// Token of the discord account to use
     #[clap(short, long, env)]
-    token: String,
+    token: Option<String>,
 
     /// The master of this bot (can control the bot)
     #[clap(short, long, env)]
@@ -27,6 +26,46 @@ struct Args {
     /// The default channel in which the bot runs
     #[clap(short, long, env)]
     channel_id: Option<String>,
+
+    /// How many times in a row to try reconnecting before giving up (0 = forever)
+    #[clap(long, env, default_value_t = DEFAULT_MAX_RECONNECT_ATTEMPTS)]
+    max_reconnect_attempts: u32,
+
+    /// File to keep the farming channel and command cooldowns in between runs
+    #[clap(long, env)]
+    state_file: Option<PathBuf>,
+
+    /// Base url of the Discord Gateway
+    #[clap(long, env, default_value = DEFAULT_GATEWAY_URL)]
+    gateway_url: String,
+
+    /// Base url of the Discord REST API
+    #[clap(long, env, default_value = DEFAULT_API_URL)]
+    api_url: String,
+
+    /// Append every gateway package and REST request to this JSONL file (token redacted)
+    #[clap(long, env)]
+    record: Option<PathBuf>,
+
+    /// Log what would be sent to the REST API instead of sending it, the gateway stays connected
+    #[clap(long, env)]
+    dry_run: bool,
+
+    /// Compress the gateway traffic (zlib-stream), saves bandwidth in large guilds
+    #[clap(long, env)]
+    compress: bool,
+
+    #[clap(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Run the command handlers against a capture made with --record, without connecting to Discord
+    Replay {
+        /// Capture file to replay
+        capture: PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -38,5 +77,59 @@ async fn main() {
         .filter_level(args.verbose.log_level_filter())
         .init();
 
-    connect(args.token, args.master_id, args.channel_id).await;
+    let token = match (&args.command, args.token) {
+        (Some(Commands::Replay { .. }), token) => token.unwrap_or_default(),
+        (None, Some(token)) => token,
+        (None, None) => Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "The token is required to connect to Discord (--token or TOKEN)",
+            )
+            .exit(),
+    };
+
+    let bot = Bot::builder(&token)
+        .master_id(args.master_id)
+        .channel_id(args.channel_id)
+        .max_reconnect_attempts(args.max_reconnect_attempts)
+        .state_file(args.state_file)
+        .gateway_url(&args.gateway_url)
+        .api_url(&args.api_url)
+        .record(args.record)
+        .dry_run(args.dry_run)
+        .compress(args.compress)
+        .build();
+
+    let shutdown = bot.shutdown_handle();
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        info!("Shutting down...");
+        shutdown.shutdown();
+    });
+
+    let result = match &args.command {
+        Some(Commands::Replay { capture }) => bot.replay(capture).await,
+        None => bot.connect().await,
+    };
+
+    if let Err(error) = result {
+        error!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+/// Returns once the program is asked to stop (Ctrl+C, or SIGTERM on unix)
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
 }
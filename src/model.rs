@@ -1,5 +1,8 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::collections::HashMap;
+
+use crate::custom_error::BotError;
 
 fn default_as_false() -> bool {
     false
@@ -27,6 +30,235 @@ pub struct HelloData {
     pub heartbeat_interval: u64,
 }
 
+/// Where the connection with the Discord Gateway currently is at
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Identifying,
+    Resuming,
+    Ready,
+}
+
+/// A Dispatch of the Discord Gateway
+#[derive(Debug, Clone)]
+pub enum GatewayEvent {
+    Ready(ReadyData),
+    Resumed,
+    MessageCreate(MessageCreateData),
+    MessageUpdate(MessageCreateData),
+    MessageDelete(MessageDeleteData),
+    MessageReactionAdd(ReactionEventData),
+    MessageReactionRemove(ReactionEventData),
+    InteractionCreate(InteractionEventData),
+    InteractionSuccess(InteractionEventData),
+    InteractionFailure(InteractionEventData),
+    SessionsReplace(Vec<SessionData>),
+    GuildCreate(GuildData),
+    ChannelCreate(ChannelData),
+    ChannelUpdate(ChannelData),
+    ChannelDelete(ChannelData),
+    /// Any event that is not known (yet), kept as it came in
+    Unknown {
+        name: String,
+        data: serde_json::Value,
+    },
+}
+
+/// Which kind of event a handler is for
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum EventKind {
+    Ready,
+    Resumed,
+    MessageCreate,
+    MessageUpdate,
+    MessageDelete,
+    MessageReactionAdd,
+    MessageReactionRemove,
+    InteractionCreate,
+    InteractionSuccess,
+    InteractionFailure,
+    SessionsReplace,
+    GuildCreate,
+    ChannelCreate,
+    ChannelUpdate,
+    ChannelDelete,
+    Unknown,
+}
+
+impl GatewayEvent {
+    /// Reads the data of a Dispatch, fails when a known event is malformed
+    pub fn parse(name: &str, data: serde_json::Value) -> Result<GatewayEvent, BotError> {
+        // Borrows the data, so it can still end up in the error
+        fn from_value<T: DeserializeOwned>(data: &serde_json::Value) -> serde_json::Result<T> {
+            T::deserialize(data)
+        }
+
+        let event = match name {
+            "READY" => from_value(&data).map(GatewayEvent::Ready),
+            "RESUMED" => Ok(GatewayEvent::Resumed),
+            "MESSAGE_CREATE" => from_value(&data).map(GatewayEvent::MessageCreate),
+            "MESSAGE_UPDATE" => from_value(&data).map(GatewayEvent::MessageUpdate),
+            "MESSAGE_DELETE" => from_value(&data).map(GatewayEvent::MessageDelete),
+            "MESSAGE_REACTION_ADD" => from_value(&data).map(GatewayEvent::MessageReactionAdd),
+            "MESSAGE_REACTION_REMOVE" => from_value(&data).map(GatewayEvent::MessageReactionRemove),
+            "INTERACTION_CREATE" => from_value(&data).map(GatewayEvent::InteractionCreate),
+            "INTERACTION_SUCCESS" => from_value(&data).map(GatewayEvent::InteractionSuccess),
+            "INTERACTION_FAILURE" => from_value(&data).map(GatewayEvent::InteractionFailure),
+            "SESSIONS_REPLACE" => from_value(&data).map(GatewayEvent::SessionsReplace),
+            "GUILD_CREATE" => from_value(&data).map(GatewayEvent::GuildCreate),
+            "CHANNEL_CREATE" => from_value(&data).map(GatewayEvent::ChannelCreate),
+            "CHANNEL_UPDATE" => from_value(&data).map(GatewayEvent::ChannelUpdate),
+            "CHANNEL_DELETE" => from_value(&data).map(GatewayEvent::ChannelDelete),
+            _ => {
+                return Ok(GatewayEvent::Unknown {
+                    name: name.to_string(),
+                    data,
+                })
+            }
+        };
+        event.map_err(|error| {
+            BotError::deserialize(&format!("{} event", name), &data.to_string(), error)
+        })
+    }
+
+    pub fn kind(&self) -> EventKind {
+        match self {
+            GatewayEvent::Ready(_) => EventKind::Ready,
+            GatewayEvent::Resumed => EventKind::Resumed,
+            GatewayEvent::MessageCreate(_) => EventKind::MessageCreate,
+            GatewayEvent::MessageUpdate(_) => EventKind::MessageUpdate,
+            GatewayEvent::MessageDelete(_) => EventKind::MessageDelete,
+            GatewayEvent::MessageReactionAdd(_) => EventKind::MessageReactionAdd,
+            GatewayEvent::MessageReactionRemove(_) => EventKind::MessageReactionRemove,
+            GatewayEvent::InteractionCreate(_) => EventKind::InteractionCreate,
+            GatewayEvent::InteractionSuccess(_) => EventKind::InteractionSuccess,
+            GatewayEvent::InteractionFailure(_) => EventKind::InteractionFailure,
+            GatewayEvent::SessionsReplace(_) => EventKind::SessionsReplace,
+            GatewayEvent::GuildCreate(_) => EventKind::GuildCreate,
+            GatewayEvent::ChannelCreate(_) => EventKind::ChannelCreate,
+            GatewayEvent::ChannelUpdate(_) => EventKind::ChannelUpdate,
+            GatewayEvent::ChannelDelete(_) => EventKind::ChannelDelete,
+            GatewayEvent::Unknown { .. } => EventKind::Unknown,
+        }
+    }
+
+    /// The message of a create or update event
+    pub fn message(&self) -> Option<&MessageCreateData> {
+        match self {
+            GatewayEvent::MessageCreate(data) | GatewayEvent::MessageUpdate(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageDeleteData {
+    pub id: String,
+    pub channel_id: String,
+    pub guild_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionEventData {
+    pub user_id: String,
+    pub channel_id: String,
+    pub message_id: String,
+    pub guild_id: Option<String>,
+    pub emoji: ReactionEmoji,
+}
+
+/// Emoji of a reaction, custom emojis have an id, unicode emojis only a name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReactionEmoji {
+    pub id: Option<String>,
+    pub name: Option<String>,
+}
+
+impl ReactionEmoji {
+    /// Whether this is the emoji, given as the unicode emoji or `name:id` for custom emojis
+    pub fn is(&self, emoji: &str) -> bool {
+        self.to_string() == emoji
+    }
+}
+
+/// Formatted like the REST API takes it, the unicode emoji or `name:id` for custom emojis
+impl std::fmt::Display for ReactionEmoji {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = self.name.as_deref().unwrap_or_default();
+        match &self.id {
+            Some(id) => write!(f, "{}:{}", name, id),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Result of an interaction (button click, select), only sent to the user that interacted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InteractionEventData {
+    pub id: String,
+    pub nonce: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionData {
+    pub session_id: String,
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GuildData {
+    pub id: String,
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelData {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub channel_type: u8,
+    pub guild_id: Option<String>,
+    pub name: Option<String>,
+}
+
+/// Body of an error response of the Discord REST API
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiErrorBody {
+    pub code: u32,
+    pub message: String,
+    /// Nested by field, only sent with validation errors
+    pub errors: Option<serde_json::Value>,
+}
+
+/// What happens to requests for the Discord REST API
+#[derive(Debug, Clone, Copy)]
+pub enum RestMode {
+    /// Send them to Discord
+    Live,
+    /// Hand a description of them to the function instead, the handlers get a made up response
+    /// (used when replaying captures)
+    Replay(fn(&str)),
+    /// Log them instead, the handlers get a made up response
+    DryRun,
+}
+
+/// What is kept on disk between runs
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PersistedState {
+    pub channel_id: Option<String>,
+    /// When each farming command was last sent, in seconds since the unix epoch
+    #[serde(default)]
+    pub last_called: HashMap<String, u64>,
+}
+
+/// A gateway session that can be resumed after a disconnect
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub session_id: String,
+    pub sequence: u64,
+    pub resume_gateway_url: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ResumeData {
     pub token: String,
@@ -34,16 +266,30 @@ pub struct ResumeData {
     pub seq: u64,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct DiscordMessagePayload {
     pub content: String,
     pub message_reference: Option<DiscordMessagePayloadReference>,
 }
 
+/// New content of a message of this account
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EditMessagePayload {
+    pub content: String,
+}
+
+/// Opens (or finds) the direct message channel with a user
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DirectMessagePayload {
+    pub recipient_id: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DiscordMessagePayloadReference {
     pub channel_id: String,
-    pub guild_id: String,
+    /// Not set for messages in direct messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guild_id: Option<String>,
     pub message_id: String,
 }
 
@@ -77,10 +323,13 @@ pub struct IdentifyData {
     pub presence: Presence,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ReadyData {
     pub user: ReadyDataUser,
     pub session_id: String,
+    /// Gateway to resume this session on, instead of the one used to identify
+    #[serde(default)]
+    pub resume_gateway_url: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -130,7 +379,7 @@ pub struct Properties {
     pub device: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MessageCreateData {
     #[serde(rename = "type")]
     pub message_create_data_type: i64,
@@ -152,9 +401,18 @@ pub struct MessageCreateData {
     pub attachments: Vec<Attachment>,
     pub member: Option<Member>,
     pub guild_id: Option<String>,
-    pub reactions: Option<Vec<serde_json::Value>>,
+    pub reactions: Option<Vec<Reaction>>,
 }
-#[derive(Serialize, Deserialize, Debug)]
+
+/// Reactions of one emoji on a message
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reaction {
+    pub count: u32,
+    /// Whether this account is one of them
+    pub me: bool,
+    pub emoji: ReactionEmoji,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Attachment {
     pub content_type: Option<String>,
     pub filename: String,
@@ -168,7 +426,7 @@ pub struct Attachment {
     pub ephemeral: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MessageCreateDataAuthor {
     pub username: String,
     pub public_flags: i64,
@@ -178,7 +436,7 @@ pub struct MessageCreateDataAuthor {
     pub avatar: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MessageCreateDataComponent {
     #[serde(rename = "type")]
     pub component_type: ComponentType,
@@ -214,7 +472,7 @@ pub struct ComponentOption {
     pub label: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Embed {
     #[serde(rename = "type")]
     pub embed_type: Option<String>,
@@ -232,7 +490,7 @@ pub struct Embed {
     pub fields: Option<Vec<EmbedField>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EmbedAuthor {
     pub name: String,
     pub url: Option<String>,
@@ -240,7 +498,7 @@ pub struct EmbedAuthor {
     pub proxy_icon_url: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EmbedImage {
     pub url: String,
     pub proxy_url: Option<String>,
@@ -250,7 +508,7 @@ pub struct EmbedImage {
 
 pub type EmbedThumbnail = EmbedImage;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EmbedVideo {
     pub url: Option<String>,
     pub proxy_url: Option<String>,
@@ -258,27 +516,27 @@ pub struct EmbedVideo {
     pub width: Option<i32>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EmbedFooter {
     pub text: String,
     pub icon_url: Option<String>,
     pub proxy_icon_url: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EmbedProvider {
     pub text: Option<String>,
     pub url: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EmbedField {
     pub name: String,
     pub value: String,
     pub inline: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Member {
     pub nick: Option<String>,
     pub avatar: Option<String>,
@@ -327,6 +585,93 @@ pub enum OpCode {
     HeartbeatAck = 11,
 }
 
+/// Close codes the Discord Gateway can end a connection with
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(u16)]
+pub enum GatewayCloseCode {
+    UnknownError = 4000,
+    UnknownOpCode = 4001,
+    DecodeError = 4002,
+    NotAuthenticated = 4003,
+    AuthenticationFailed = 4004,
+    AlreadyAuthenticated = 4005,
+    InvalidSequence = 4007,
+    RateLimited = 4008,
+    SessionTimedOut = 4009,
+    InvalidShard = 4010,
+    ShardingRequired = 4011,
+    InvalidApiVersion = 4012,
+    InvalidIntents = 4013,
+    DisallowedIntents = 4014,
+}
+
+impl GatewayCloseCode {
+    pub fn from_code(code: u16) -> Option<GatewayCloseCode> {
+        match code {
+            4000 => Some(GatewayCloseCode::UnknownError),
+            4001 => Some(GatewayCloseCode::UnknownOpCode),
+            4002 => Some(GatewayCloseCode::DecodeError),
+            4003 => Some(GatewayCloseCode::NotAuthenticated),
+            4004 => Some(GatewayCloseCode::AuthenticationFailed),
+            4005 => Some(GatewayCloseCode::AlreadyAuthenticated),
+            4007 => Some(GatewayCloseCode::InvalidSequence),
+            4008 => Some(GatewayCloseCode::RateLimited),
+            4009 => Some(GatewayCloseCode::SessionTimedOut),
+            4010 => Some(GatewayCloseCode::InvalidShard),
+            4011 => Some(GatewayCloseCode::ShardingRequired),
+            4012 => Some(GatewayCloseCode::InvalidApiVersion),
+            4013 => Some(GatewayCloseCode::InvalidIntents),
+            4014 => Some(GatewayCloseCode::DisallowedIntents),
+            _ => None,
+        }
+    }
+
+    /// Reconnecting will never work after these (e.g. the token got revoked)
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            GatewayCloseCode::AuthenticationFailed
+                | GatewayCloseCode::InvalidShard
+                | GatewayCloseCode::ShardingRequired
+                | GatewayCloseCode::InvalidApiVersion
+                | GatewayCloseCode::InvalidIntents
+                | GatewayCloseCode::DisallowedIntents
+        )
+    }
+
+    /// The session can not be resumed after these, a fresh Identify is needed
+    pub fn requires_new_session(&self) -> bool {
+        matches!(
+            self,
+            GatewayCloseCode::InvalidSequence
+                | GatewayCloseCode::RateLimited
+                | GatewayCloseCode::SessionTimedOut
+        )
+    }
+}
+
+impl std::fmt::Display for GatewayCloseCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let reason = match self {
+            GatewayCloseCode::UnknownError => "unknown error",
+            GatewayCloseCode::UnknownOpCode => "sent an invalid opcode",
+            GatewayCloseCode::DecodeError => "sent an invalid payload",
+            GatewayCloseCode::NotAuthenticated => "sent a payload before identifying",
+            GatewayCloseCode::AuthenticationFailed => "the token is invalid",
+            GatewayCloseCode::AlreadyAuthenticated => "identified more than once",
+            GatewayCloseCode::InvalidSequence => "resumed with an invalid sequence",
+            GatewayCloseCode::RateLimited => "sent payloads too quickly",
+            GatewayCloseCode::SessionTimedOut => "the session timed out",
+            GatewayCloseCode::InvalidShard => "sent an invalid shard",
+            GatewayCloseCode::ShardingRequired => "sharding is required",
+            GatewayCloseCode::InvalidApiVersion => "the gateway version is invalid",
+            GatewayCloseCode::InvalidIntents => "sent invalid intents",
+            GatewayCloseCode::DisallowedIntents => "sent disallowed intents",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
 #[derive(Deserialize_repr, Serialize_repr, Debug, PartialEq, Clone, Copy)]
 #[repr(u8)]
 pub enum ComponentType {
@@ -0,0 +1,98 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::error;
+use serde_json::{json, Value};
+
+use crate::custom_error::BotError;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Appends the gateway and REST traffic to a JSONL file, one entry per line
+///
+/// The token never ends up in the file, it is replaced wherever it shows up.
+pub struct Recorder {
+    file: Mutex<File>,
+    token: String,
+}
+
+impl Recorder {
+    pub fn open(path: &Path, token: &str) -> Result<Recorder, BotError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|error| BotError::io(path, error))?;
+        Ok(Recorder {
+            file: Mutex::new(file),
+            token: token.to_string(),
+        })
+    }
+
+    /// Records a raw package received from the gateway
+    pub fn record_package(&self, raw: &str) {
+        self.write(json!({
+            "direction": "inbound",
+            "kind": "gateway",
+            "package": parse(raw),
+        }));
+    }
+
+    /// Records a request sent to the REST API, with the status code of the response
+    pub fn record_request(&self, method: &str, url: &str, body: &str, status: Option<u16>) {
+        self.write(json!({
+            "direction": "outbound",
+            "kind": "rest",
+            "method": method,
+            "url": url,
+            "body": parse(body),
+            "status": status,
+        }));
+    }
+
+    fn write(&self, mut entry: Value) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        entry["timestamp"] = Value::from(timestamp);
+        self.redact(&mut entry);
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(error) = writeln!(file, "{}", entry) {
+            error!("Could not write recording: {}", error);
+        }
+    }
+
+    /// Replaces tokens anywhere in the value
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if key.eq_ignore_ascii_case("token")
+                        || key.eq_ignore_ascii_case("authorization")
+                    {
+                        *value = Value::from(REDACTED);
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact(value)),
+            Value::String(string) if !self.token.is_empty() && string.contains(&self.token) => {
+                *string = string.replace(&self.token, REDACTED);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Keeps JSON as JSON, anything else as a string
+fn parse(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::from(raw))
+}
@@ -0,0 +1,145 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    time::Duration,
+};
+
+use log::{info, warn};
+use serde_json::Value;
+use tokio::sync::watch;
+
+use crate::{
+    custom_error::BotError,
+    discord_client::{create_client, handle_ws_package, ClientConfig},
+    discord_commands::CommandRegistry,
+    model::*,
+};
+
+/// Longest pause between two replayed events, captures can be idle for a long time
+const MAX_REPLAY_GAP: Duration = Duration::from_secs(1);
+
+/// Events fed to the handlers, everything else in the capture is skipped
+const REPLAYED_EVENTS: [&str; 5] = [
+    "READY",
+    "MESSAGE_CREATE",
+    "MESSAGE_UPDATE",
+    "MESSAGE_REACTION_ADD",
+    "MESSAGE_REACTION_REMOVE",
+];
+
+/// Prints what a handler would have sent to Discord
+fn print_request(description: &str) {
+    println!("{}", description);
+}
+
+/// Runs the command handlers against a capture made with `--record`
+///
+/// Nothing is sent to Discord, the clicks, selects and messages of the handlers are printed instead.
+/// Stops early, dropping the running handlers, once `shutdown` flips to true.
+pub async fn replay(
+    config: ClientConfig,
+    registry: CommandRegistry,
+    capture: &Path,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), BotError> {
+    let file = File::open(capture).map_err(|error| BotError::io(capture, error))?;
+
+    let (shared_client, _master_command_receiver) = create_client(
+        &config,
+        registry,
+        &PersistedState::default(),
+        RestMode::Replay(print_request),
+    )?;
+
+    let mut handlers = vec![];
+    let mut last_timestamp: Option<u64> = None;
+    let mut replayed = 0;
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        if *shutdown.borrow() {
+            break;
+        }
+        let line = line.map_err(|error| BotError::io(capture, error))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry: Value = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(error) => {
+                warn!("Skipping line {}: {}", index + 1, error);
+                continue;
+            }
+        };
+        if entry["direction"] != "inbound" || entry["kind"] != "gateway" {
+            continue;
+        }
+        let package: Package = match serde_json::from_value(entry["package"].clone()) {
+            Ok(package) => package,
+            Err(error) => {
+                warn!("Skipping line {}: {}", index + 1, error);
+                continue;
+            }
+        };
+        let event = match (&package.op, &package.tag) {
+            (OpCode::Dispatch, Some(event)) if REPLAYED_EVENTS.contains(&event.as_str()) => {
+                event.to_string()
+            }
+            _ => continue,
+        };
+        if event != "READY" && shared_client.user().is_none() {
+            warn!("Skipping {} on line {}, no READY yet", event, index + 1);
+            continue;
+        }
+
+        // Keep the order and roughly the timing, handlers wait for updates
+        let timestamp = entry["timestamp"].as_u64();
+        if let (Some(last), Some(timestamp)) = (last_timestamp, timestamp) {
+            let gap = Duration::from_millis(timestamp.saturating_sub(last)).min(MAX_REPLAY_GAP);
+            tokio::select! {
+                _ = tokio::time::sleep(gap) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+        last_timestamp = timestamp.or(last_timestamp);
+
+        let data = package.data.as_ref();
+        println!(
+            "{} {} {}",
+            event,
+            data.and_then(|data| data["id"].as_str())
+                .unwrap_or_default(),
+            data.and_then(|data| data["content"].as_str())
+                .unwrap_or_default()
+        );
+        replayed += 1;
+
+        if let Some(follow_up) = handle_ws_package(shared_client.clone(), package).await {
+            handlers.push(tokio::spawn(follow_up));
+        }
+    }
+
+    let mut stopped = *shutdown.borrow();
+    for mut handler in handlers {
+        if !stopped {
+            tokio::select! {
+                _ = &mut handler => continue,
+                _ = shutdown.changed() => stopped = true,
+            }
+        }
+        handler.abort();
+        handler.await.ok();
+    }
+
+    if stopped {
+        info!(
+            "Stopped after {} events from {}",
+            replayed,
+            capture.display()
+        );
+    } else {
+        info!("Replayed {} events from {}", replayed, capture.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,676 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use reqwest::{header::HeaderMap, Method, StatusCode};
+use tokio::{sync::OwnedMutexGuard, time::Instant};
+
+use crate::{custom_error::BotError, discord_message::DiscordClient, model::*};
+
+/// How often a rate limited request is tried again before giving up
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// Wait when Discord rate limits without saying for how long
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Channel type of a direct message
+const DM_CHANNEL_TYPE: u8 = 1;
+
+/// Id of the next made up message, for requests that are not sent to Discord
+static NEXT_FAKE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Keeps track of the rate limits Discord reports, so requests wait instead of getting a 429
+#[derive(Default)]
+pub struct RateLimiter {
+    /// Bucket of every route used so far
+    buckets: Mutex<HashMap<String, Arc<Bucket>>>,
+    /// Every request waits until then, set by a global 429
+    global_reset: Mutex<Option<Instant>>,
+}
+
+#[derive(Default)]
+struct Bucket {
+    /// Lines up the requests of the route, held while one waits for the reset or finds out
+    /// the limits, responses never need it
+    queue: Arc<tokio::sync::Mutex<()>>,
+    limits: Mutex<BucketLimits>,
+}
+
+#[derive(Default)]
+struct BucketLimits {
+    /// Requests left until `reset`, unknown before the first response
+    remaining: Option<u32>,
+    reset: Option<Instant>,
+}
+
+/// What a response says about the rate limit of its bucket
+struct RateLimitHeaders {
+    remaining: Option<u32>,
+    reset_after: Option<Duration>,
+    retry_after: Option<Duration>,
+    global: bool,
+}
+
+impl RateLimitHeaders {
+    fn parse(headers: &HeaderMap) -> RateLimitHeaders {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let seconds = |value: &str| value.parse::<f64>().ok().map(Duration::from_secs_f64);
+        RateLimitHeaders {
+            remaining: header("X-RateLimit-Remaining").and_then(|value| value.parse().ok()),
+            reset_after: header("X-RateLimit-Reset-After").and_then(seconds),
+            retry_after: header("Retry-After").and_then(seconds),
+            global: header("X-RateLimit-Global") == Some("true")
+                || header("X-RateLimit-Scope") == Some("global"),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Waits until the bucket of the route has a request left and takes it
+    ///
+    /// While its limits are unknown the queue of the bucket is returned, it stays locked until
+    /// the response tells them, so only one request finds out instead of all of them getting a 429.
+    async fn acquire(&self, route: &str) -> Option<OwnedMutexGuard<()>> {
+        let bucket = self.bucket(route);
+        // Held while waiting, so the requests of a bucket go out in order
+        let queue = bucket.queue.clone().lock_owned().await;
+        loop {
+            self.wait_global().await;
+            let reset = {
+                let limits = bucket.limits.lock().unwrap();
+                limits
+                    .reset
+                    .filter(|reset| limits.remaining == Some(0) && *reset > Instant::now())
+            };
+            match reset {
+                Some(reset) => {
+                    debug!(
+                        "Rate limited on {}, waiting {:?}",
+                        route,
+                        reset - Instant::now()
+                    );
+                    // Checked again after, a 429 or a global one may have come in meanwhile
+                    tokio::time::sleep_until(reset).await;
+                }
+                None => break,
+            }
+        }
+
+        let mut limits = bucket.limits.lock().unwrap();
+        if limits.remaining == Some(0) {
+            // A new window, the next response tells how much of it is left
+            limits.remaining = None;
+        }
+        match limits.remaining.as_mut() {
+            Some(remaining) => {
+                *remaining = remaining.saturating_sub(1);
+                None
+            }
+            None => Some(queue),
+        }
+    }
+
+    /// Waits until a global rate limit is over, also when it gets extended while waiting
+    async fn wait_global(&self) {
+        loop {
+            let global_reset = *self.global_reset.lock().unwrap();
+            match global_reset.filter(|reset| *reset > Instant::now()) {
+                Some(reset) => {
+                    debug!(
+                        "Globally rate limited, waiting {:?}",
+                        reset - Instant::now()
+                    );
+                    tokio::time::sleep_until(reset).await;
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Takes in the rate limit headers of a response, lets the next request of an unknown
+    /// bucket go once its limits are known
+    fn update(
+        &self,
+        route: &str,
+        headers: &RateLimitHeaders,
+        unknown_bucket: Option<OwnedMutexGuard<()>>,
+    ) {
+        let bucket = self.bucket(route);
+        let mut limits = bucket.limits.lock().unwrap();
+        let same_window = limits.reset.is_some_and(|reset| reset > Instant::now());
+        limits.remaining = match (headers.remaining, limits.remaining) {
+            // Requests that are still on their way already took theirs
+            (Some(remaining), Some(left)) if same_window => Some(remaining.min(left)),
+            (Some(remaining), _) => Some(remaining),
+            (None, left) => left,
+        };
+        if let Some(reset_after) = headers.reset_after {
+            limits.reset = Some(Instant::now() + reset_after);
+        }
+        drop(unknown_bucket);
+    }
+
+    /// Makes the bucket (or every request) wait after a 429
+    fn limited(&self, route: &str, headers: &RateLimitHeaders) -> Duration {
+        let retry_after = headers
+            .retry_after
+            .or(headers.reset_after)
+            .unwrap_or(DEFAULT_RETRY_AFTER);
+        let reset = Instant::now() + retry_after;
+        if headers.global {
+            *self.global_reset.lock().unwrap() = Some(reset);
+        } else {
+            let bucket = self.bucket(route);
+            let mut limits = bucket.limits.lock().unwrap();
+            limits.remaining = Some(0);
+            limits.reset = Some(reset);
+        }
+        retry_after
+    }
+
+    fn bucket(&self, route: &str) -> Arc<Bucket> {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.entry(route.to_string()).or_default().clone()
+    }
+}
+
+/// Rate limit route of a request, method and path without the ids
+///
+/// The channel or guild id stays, every channel and guild has buckets of its own.
+fn route(method: &Method, path: &str) -> String {
+    let path = path.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let major_parameter = matches!(segments.first(), Some(&"channels" | &"guilds"));
+    let path = segments
+        .iter()
+        .enumerate()
+        .map(|(index, segment)| {
+            let is_id = !segment.is_empty() && segment.chars().all(|char| char.is_ascii_digit());
+            if is_id && !(major_parameter && index == 1) {
+                ":id"
+            } else if index > 0 && segments[index - 1] == "reactions" {
+                // All emojis of a message share the bucket
+                ":emoji"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join("/");
+    format!("{} /{}", method, path)
+}
+
+/// Sends a request to the Discord REST API, the path is relative to the API url
+///
+/// Waits for the rate limit of the route before sending and tries again after a 429,
+/// fails with `BotError::RateLimited` when that keeps happening.
+/// Any other error status fails with `BotError::Http`, carrying what Discord said was wrong.
+pub async fn request(
+    client: &DiscordClient,
+    method: Method,
+    path: &str,
+    body: Option<String>,
+) -> Result<reqwest::Response, BotError> {
+    let url = format!("{}{}", client.api_url, path);
+    let body = body.unwrap_or_default();
+    match client.rest_mode {
+        RestMode::Live => {}
+        RestMode::Replay(on_request) => {
+            on_request(&describe_request(&method, path, &body));
+            return Ok(fake_response(client, &method, path, &body));
+        }
+        RestMode::DryRun => {
+            info!("[dry run] {}", describe_request(&method, path, &body));
+            info!("[dry run] {} {} {}", method, url, body);
+            return Ok(fake_response(client, &method, path, &body));
+        }
+    }
+
+    let route = route(&method, path);
+    let mut attempt = 0;
+    loop {
+        let unknown_bucket = client.rate_limits.acquire(&route).await;
+
+        let mut request = client.http.request(method.clone(), &url);
+        if !body.is_empty() {
+            request = request.body(body.clone());
+        }
+        let response = request.send().await;
+        if let Some(recorder) = &client.recorder {
+            let status = response
+                .as_ref()
+                .ok()
+                .map(|response| response.status().as_u16());
+            recorder.record_request(method.as_str(), &url, &body, status);
+        }
+        let response = response?;
+
+        let headers = RateLimitHeaders::parse(response.headers());
+        client.rate_limits.update(&route, &headers, unknown_bucket);
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            let status = response.status();
+            let body = response.text().await?;
+            return Err(BotError::http(
+                format!("{} {}", method, path),
+                status,
+                &body,
+            ));
+        }
+
+        let retry_after = client.rate_limits.limited(&route, &headers);
+        if attempt >= MAX_RATE_LIMIT_RETRIES {
+            return Err(BotError::RateLimited {
+                route,
+                retry_after,
+                global: headers.global,
+            });
+        }
+        attempt += 1;
+        warn!(
+            "Rate limited{} on {}, trying again in {:?}",
+            if headers.global { " globally" } else { "" },
+            route,
+            retry_after
+        );
+    }
+}
+
+/// Sends a POST request to the Discord REST API, see `request`
+pub async fn post(
+    client: &DiscordClient,
+    path: &str,
+    body: String,
+) -> Result<reqwest::Response, BotError> {
+    request(client, Method::POST, path, Some(body)).await
+}
+
+/// Short summary of what a request to the REST API does
+pub fn describe_request(method: &Method, path: &str, body: &str) -> String {
+    let payload: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+    match (method, message_path(path)) {
+        (&Method::POST, _) if path == "/interactions" => {
+            let data = &payload["data"];
+            if data["values"].is_array() {
+                format!(
+                    "Select {} in {} on message {}",
+                    data["values"], data["custom_id"], payload["message_id"]
+                )
+            } else {
+                format!(
+                    "Click {} on message {}",
+                    data["custom_id"], payload["message_id"]
+                )
+            }
+        }
+        (&Method::POST, Some((channel_id, None))) => {
+            match payload["message_reference"]["message_id"].as_str() {
+                Some(message_id) => format!(
+                    "Reply to message {} in channel {}: {}",
+                    message_id, channel_id, payload["content"]
+                ),
+                None => format!("Send to channel {}: {}", channel_id, payload["content"]),
+            }
+        }
+        (&Method::PATCH, Some((channel_id, Some(message_id)))) => format!(
+            "Edit message {} in channel {}: {}",
+            message_id, channel_id, payload["content"]
+        ),
+        (&Method::DELETE, Some((channel_id, Some(message_id)))) => {
+            format!("Delete message {} in channel {}", message_id, channel_id)
+        }
+        (&Method::GET, Some((channel_id, None))) => {
+            format!("Read the messages of channel {}", channel_id)
+        }
+        (&Method::POST, _) if path == "/users/@me/channels" => {
+            format!("Open a direct message with {}", payload["recipient_id"])
+        }
+        _ => match (method, own_reaction_path(path)) {
+            (&Method::PUT, Some((channel_id, message_id, emoji))) => format!(
+                "React {} to message {} in channel {}",
+                emoji, message_id, channel_id
+            ),
+            (&Method::DELETE, Some((channel_id, message_id, emoji))) => format!(
+                "Remove reaction {} from message {} in channel {}",
+                emoji, message_id, channel_id
+            ),
+            _ => format!("{} {} {}", method, path, body),
+        },
+    }
+}
+
+/// Channel, message and emoji of a path to a reaction of this account
+fn own_reaction_path(path: &str) -> Option<(&str, &str, String)> {
+    let segments: Vec<&str> = path.strip_prefix("/channels/")?.split('/').collect();
+    match segments.as_slice() {
+        [channel_id, "messages", message_id, "reactions", emoji, "@me"] => {
+            Some((channel_id, message_id, decode_path_segment(emoji)))
+        }
+        _ => None,
+    }
+}
+
+/// Percent-encodes everything but unreserved characters and `:` (which separates custom emojis from their id)
+pub fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn decode_path_segment(segment: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Channel and message of a path to the messages of a channel, no message for the whole channel
+fn message_path(path: &str) -> Option<(&str, Option<&str>)> {
+    let path = path.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.strip_prefix("/channels/")?.split('/').collect();
+    match segments.as_slice() {
+        [channel_id, "messages"] => Some((channel_id, None)),
+        [channel_id, "messages", message_id] => Some((channel_id, Some(message_id))),
+        _ => None,
+    }
+}
+
+/// What Discord would have answered to a request that was not sent
+fn fake_response(
+    client: &DiscordClient,
+    method: &Method,
+    path: &str,
+    body: &str,
+) -> reqwest::Response {
+    let message = match (method, message_path(path)) {
+        (&Method::POST, Some((channel_id, None))) => {
+            let payload: DiscordMessagePayload = serde_json::from_str(body).unwrap();
+            let id = NEXT_FAKE_ID.fetch_add(1, Ordering::Relaxed).to_string();
+            let guild_id = payload
+                .message_reference
+                .and_then(|reference| reference.guild_id);
+            fake_message(client, channel_id, id, payload.content, guild_id)
+        }
+        (&Method::PATCH, Some((channel_id, Some(message_id)))) => {
+            let content = serde_json::from_str::<EditMessagePayload>(body)
+                .map(|payload| payload.content)
+                .unwrap_or_default();
+            fake_message(client, channel_id, message_id.to_string(), content, None)
+        }
+        (&Method::POST, _) if path == "/users/@me/channels" => {
+            let channel = ChannelData {
+                id: NEXT_FAKE_ID.fetch_add(1, Ordering::Relaxed).to_string(),
+                channel_type: DM_CHANNEL_TYPE,
+                guild_id: None,
+                name: None,
+            };
+            return http::Response::builder()
+                .status(200)
+                .body(serde_json::to_string(&channel).unwrap())
+                .unwrap()
+                .into();
+        }
+        // Nothing was sent, so there is nothing to read either
+        (&Method::GET, Some((_, None))) => {
+            return http::Response::builder()
+                .status(200)
+                .body("[]")
+                .unwrap()
+                .into()
+        }
+        _ => {
+            return http::Response::builder()
+                .status(204)
+                .body("")
+                .unwrap()
+                .into()
+        }
+    };
+    http::Response::builder()
+        .status(200)
+        .body(serde_json::to_string(&message).unwrap())
+        .unwrap()
+        .into()
+}
+
+/// Message of the logged in user, as Discord would return it
+fn fake_message(
+    client: &DiscordClient,
+    channel_id: &str,
+    id: String,
+    content: String,
+    guild_id: Option<String>,
+) -> MessageCreateData {
+    let user = client.user();
+    let user = user.as_ref();
+    MessageCreateData {
+        message_create_data_type: 0,
+        tts: false,
+        timestamp: String::new(),
+        referenced_message: None,
+        pinned: false,
+        mentions: vec![],
+        mention_roles: vec![],
+        mention_everyone: false,
+        id,
+        flags: 0,
+        embeds: vec![],
+        edited_timestamp: None,
+        content,
+        components: vec![],
+        channel_id: channel_id.to_string(),
+        author: MessageCreateDataAuthor {
+            username: user.map(|user| user.username.clone()).unwrap_or_default(),
+            public_flags: 0,
+            id: user.map(|user| user.id.clone()).unwrap_or_default(),
+            discriminator: user
+                .map(|user| user.discriminator.clone())
+                .unwrap_or_default(),
+            bot: None,
+            avatar: None,
+        },
+        attachments: vec![],
+        member: None,
+        guild_id,
+        reactions: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(remaining: Option<u32>, reset_after: Option<f64>) -> RateLimitHeaders {
+        RateLimitHeaders {
+            remaining,
+            reset_after: reset_after.map(Duration::from_secs_f64),
+            retry_after: None,
+            global: false,
+        }
+    }
+
+    fn retry_after(seconds: f64, global: bool) -> RateLimitHeaders {
+        RateLimitHeaders {
+            remaining: Some(0),
+            reset_after: None,
+            retry_after: Some(Duration::from_secs_f64(seconds)),
+            global,
+        }
+    }
+
+    #[test]
+    fn route_keeps_major_parameters() {
+        assert_eq!(
+            route(&Method::GET, "/channels/123/messages/456?limit=10"),
+            "GET /channels/123/messages/:id"
+        );
+        assert_eq!(
+            route(&Method::PATCH, "/guilds/789/members/456"),
+            "PATCH /guilds/789/members/:id"
+        );
+        assert_eq!(route(&Method::GET, "/users/456"), "GET /users/:id");
+        assert_eq!(route(&Method::POST, "/interactions"), "POST /interactions");
+    }
+
+    #[test]
+    fn route_shares_the_bucket_of_all_emojis() {
+        let check = route(
+            &Method::PUT,
+            "/channels/1/messages/2/reactions/%E2%9C%85/@me",
+        );
+        let custom = route(
+            &Method::PUT,
+            "/channels/1/messages/2/reactions/pepe:123/@me",
+        );
+        assert_eq!(check, "PUT /channels/1/messages/:id/reactions/:emoji/@me");
+        assert_eq!(check, custom);
+    }
+
+    #[test]
+    fn path_segments_round_trip() {
+        for segment in ["\u{2705}", "pepe:123", "a b/c%"] {
+            assert_eq!(decode_path_segment(&encode_path_segment(segment)), segment);
+        }
+        assert_eq!(encode_path_segment("\u{2705}"), "%E2%9C%85");
+        assert_eq!(encode_path_segment("pepe:123"), "pepe:123");
+        // Not an escape, kept as it is
+        assert_eq!(decode_path_segment("100%"), "100%");
+        assert_eq!(decode_path_segment("%zz"), "%zz");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unknown_bucket_stays_locked_until_the_response() {
+        let limiter = RateLimiter::default();
+        let unknown = limiter.acquire("GET /a").await;
+        assert!(unknown.is_some());
+
+        let bucket = limiter.bucket("GET /a");
+        assert!(bucket.queue.try_lock().is_err());
+        limiter.update("GET /a", &headers(Some(1), Some(1.0)), unknown);
+        assert!(bucket.queue.try_lock().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire_waits_for_the_reset_of_an_empty_bucket() {
+        let limiter = RateLimiter::default();
+        let unknown = limiter.acquire("GET /a").await;
+        limiter.update("GET /a", &headers(Some(1), Some(2.0)), unknown);
+
+        let start = Instant::now();
+        assert!(limiter.acquire("GET /a").await.is_none());
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // The last request of the window was taken, the next one waits for the reset
+        let unknown = limiter.acquire("GET /a").await;
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(unknown.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn update_keeps_requests_taken_in_the_same_window() {
+        let limiter = RateLimiter::default();
+        let unknown = limiter.acquire("GET /a").await;
+        limiter.update("GET /a", &headers(Some(5), Some(10.0)), unknown);
+        limiter.acquire("GET /a").await;
+        limiter.acquire("GET /a").await;
+
+        // Answer to the first of the two, sent before the second was taken
+        limiter.update("GET /a", &headers(Some(4), Some(10.0)), None);
+        let bucket = limiter.bucket("GET /a");
+        assert_eq!(bucket.limits.lock().unwrap().remaining, Some(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn responses_do_not_wait_for_queued_requests() {
+        let limiter = Arc::new(RateLimiter::default());
+        let unknown = limiter.acquire("POST /interactions").await;
+        limiter.update("POST /interactions", &headers(Some(1), Some(5.0)), unknown);
+        // On its way, took the last request of the window
+        assert!(limiter.acquire("POST /interactions").await.is_none());
+
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire("POST /interactions").await.is_some() }
+        });
+        tokio::task::yield_now().await;
+
+        // The response of the request on its way comes in while the next one waits for the reset
+        let start = Instant::now();
+        limiter.update("POST /interactions", &headers(Some(0), Some(5.0)), None);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert!(!waiting.is_finished());
+
+        assert!(waiting.await.unwrap());
+        assert!(start.elapsed() >= Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limited_makes_the_bucket_wait() {
+        let limiter = RateLimiter::default();
+        let waited = limiter.limited("GET /a", &retry_after(3.0, false));
+        assert_eq!(waited, Duration::from_secs(3));
+
+        let start = Instant::now();
+        limiter.acquire("GET /b").await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire("GET /a").await;
+        assert!(start.elapsed() >= Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn global_limit_makes_every_route_wait() {
+        let limiter = RateLimiter::default();
+        limiter.limited("GET /a", &retry_after(2.0, true));
+
+        let start = Instant::now();
+        limiter.acquire("GET /b").await;
+        assert!(start.elapsed() >= Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn global_limit_applies_to_requests_waiting_for_their_bucket() {
+        let limiter = Arc::new(RateLimiter::default());
+        let unknown = limiter.acquire("GET /a").await;
+
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move {
+                let start = Instant::now();
+                limiter.acquire("GET /a").await;
+                start.elapsed()
+            }
+        });
+        tokio::task::yield_now().await;
+
+        // The global 429 comes in after the second request checked the global limit
+        limiter.limited("GET /c", &retry_after(5.0, true));
+        limiter.update("GET /a", &headers(Some(10), Some(1.0)), unknown);
+        assert!(waiting.await.unwrap() >= Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,93 @@
+use std::io::Write;
+
+use flate2::write::ZlibDecoder;
+
+use crate::custom_error::BotError;
+
+/// Every complete package of a `zlib-stream` connection ends with this Z_SYNC_FLUSH marker
+const ZLIB_SUFFIX: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Inflates the binary frames of a gateway connection with `zlib-stream` transport compression
+///
+/// All frames of a connection share one zlib context, so it has to live as long as the connection.
+pub struct ZlibStream {
+    decoder: ZlibDecoder<Vec<u8>>,
+    /// Frames of a package that is not complete yet
+    buffer: Vec<u8>,
+}
+
+impl Default for ZlibStream {
+    fn default() -> Self {
+        ZlibStream {
+            decoder: ZlibDecoder::new(vec![]),
+            buffer: vec![],
+        }
+    }
+}
+
+impl ZlibStream {
+    /// Adds a binary frame, returns the package once all of its frames arrived
+    pub fn push(&mut self, frame: &[u8]) -> Result<Option<String>, BotError> {
+        self.buffer.extend_from_slice(frame);
+        if !self.buffer.ends_with(&ZLIB_SUFFIX) {
+            return Ok(None);
+        }
+
+        let inflated = self
+            .decoder
+            .write_all(&self.buffer)
+            .and_then(|_| self.decoder.flush());
+        self.buffer.clear();
+        if let Err(error) = inflated {
+            return Err(BotError::Decompress(error.to_string()));
+        }
+
+        let package = std::mem::take(self.decoder.get_mut());
+        String::from_utf8(package)
+            .map(Some)
+            .map_err(|error| BotError::Decompress(format!("package is not UTF-8, {}", error)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    /// Compresses a package like the gateway does, ending it with a sync flush
+    fn compress(encoder: &mut ZlibEncoder<Vec<u8>>, package: &str) -> Vec<u8> {
+        encoder.write_all(package.as_bytes()).unwrap();
+        encoder.flush().unwrap();
+        std::mem::take(encoder.get_mut())
+    }
+
+    #[test]
+    fn package_split_over_frames() {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        let package = r#"{"op":10,"d":{"heartbeat_interval":41250}}"#;
+        let compressed = compress(&mut encoder, package);
+        assert!(compressed.ends_with(&ZLIB_SUFFIX));
+
+        let mut stream = ZlibStream::default();
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        assert_eq!(stream.push(first).unwrap(), None);
+        assert_eq!(stream.push(second).unwrap().as_deref(), Some(package));
+    }
+
+    #[test]
+    fn packages_share_the_zlib_context() {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        let mut stream = ZlibStream::default();
+        let package = r#"{"op":0,"t":"MESSAGE_CREATE","d":{"content":"pls beg"}}"#;
+
+        let first = compress(&mut encoder, package);
+        let second = compress(&mut encoder, package);
+        // The second package refers back to the first one, it can not be read on its own
+        assert!(second.len() < first.len());
+        assert!(ZlibStream::default().push(&second).is_err());
+
+        assert_eq!(stream.push(&first).unwrap().as_deref(), Some(package));
+        assert_eq!(stream.push(&second).unwrap().as_deref(), Some(package));
+    }
+}
@@ -0,0 +1,223 @@
+//! Runs the bot against the mock Discord server and inspects what the mock saw
+
+// Every test file only uses some of the helpers
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use pepe_bot::{custom_error::BotError, Bot, ShutdownHandle};
+use serde_json::{json, Value};
+use tokio::{task::JoinHandle, time::Instant};
+
+pub const MASTER_ID: &str = "42";
+pub const CHANNEL_ID: &str = "5";
+/// Defaults of the mock
+pub const BOT_ID: &str = "900000000000000010";
+pub const GUILD_ID: &str = "900000000000000001";
+
+/// How long `wait_for` waits before failing the test
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A `mock-discord` process, killed when dropped
+pub struct Mock {
+    process: Child,
+    pub gateway_url: String,
+    pub api_url: String,
+    /// Base url of the `/mock/*` routes
+    control_url: String,
+    http: reqwest::Client,
+    next_message_id: u64,
+}
+
+/// A bot connected to the mock
+pub struct RunningBot {
+    shutdown: ShutdownHandle,
+    task: JoinHandle<Result<(), BotError>>,
+}
+
+impl Mock {
+    /// Starts the mock on free ports and reads its urls from stdout
+    pub fn start() -> Mock {
+        let mut process = Command::new(env!("CARGO_BIN_EXE_mock-discord"))
+            .args(["--gateway-port", "0", "--api-port", "0"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start mock-discord");
+
+        let mut gateway_url = None;
+        let mut api_url = None;
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+        for line in stdout.lines() {
+            let line = line.unwrap();
+            if let Some(url) = line.strip_prefix("GATEWAY_URL=") {
+                gateway_url = Some(url.to_string());
+            } else if let Some(url) = line.strip_prefix("API_URL=") {
+                api_url = Some(url.to_string());
+            }
+            if gateway_url.is_some() && api_url.is_some() {
+                break;
+            }
+        }
+
+        let api_url = api_url.expect("mock-discord did not print API_URL");
+        Mock {
+            process,
+            gateway_url: gateway_url.expect("mock-discord did not print GATEWAY_URL"),
+            control_url: api_url.trim_end_matches("/api/v9").to_string(),
+            api_url,
+            http: reqwest::Client::new(),
+            next_message_id: 100,
+        }
+    }
+
+    /// Connects a bot with the built-in commands and waits until it identified
+    pub async fn connect_bot(&self) -> RunningBot {
+        let bot = Bot::builder("token")
+            .master_id(Some(MASTER_ID.to_string()))
+            .gateway_url(&self.gateway_url)
+            .api_url(&self.api_url)
+            .build();
+        let shutdown = bot.shutdown_handle();
+        let task = tokio::spawn(bot.connect());
+
+        self.wait_for("/mock/sessions", |sessions| {
+            sessions["sessions"].as_u64() > Some(0)
+        })
+        .await;
+        RunningBot { shutdown, task }
+    }
+
+    pub async fn get(&self, path: &str) -> Value {
+        self.http
+            .get(format!("{}{}", self.control_url, path))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    /// Sends to a `/mock/*` route, returns the JSON answer (null for empty ones)
+    pub async fn post(&self, path: &str, body: Value) -> Value {
+        let response = self
+            .http
+            .post(format!("{}{}", self.control_url, path))
+            .body(body.to_string())
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "{} failed", path);
+        response.json().await.unwrap_or(Value::Null)
+    }
+
+    /// Polls a `/mock/*` route until the answer passes the check and returns it
+    pub async fn wait_for(&self, path: &str, check: impl Fn(&Value) -> bool) -> Value {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let value = self.get(path).await;
+            if check(&value) {
+                return value;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "Timed out on {}: {}",
+                path,
+                value
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Waits for a REST request of the bot that passes the check
+    pub async fn wait_for_request(&self, check: impl Fn(&Value) -> bool) -> Value {
+        let requests = self
+            .wait_for("/mock/requests", |requests| {
+                requests.as_array().unwrap().iter().any(&check)
+            })
+            .await;
+        requests
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|request| check(request))
+            .cloned()
+            .unwrap()
+    }
+
+    /// Sends a message of the master that mentions the bot, returns its id
+    pub async fn command(&mut self, command: &str) -> String {
+        self.message_from_master(command, Some(GUILD_ID)).await
+    }
+
+    /// Sends a command like `command`, but in a direct message
+    pub async fn direct_command(&mut self, command: &str) -> String {
+        self.message_from_master(command, None).await
+    }
+
+    async fn message_from_master(&mut self, command: &str, guild_id: Option<&str>) -> String {
+        self.next_message_id += 1;
+        let id = self.next_message_id.to_string();
+        self.post(
+            "/mock/dispatch",
+            json!({
+                "t": "MESSAGE_CREATE",
+                "d": {
+                    "id": id,
+                    "type": 0,
+                    "content": format!("<@!{}> {}", BOT_ID, command),
+                    "channel_id": CHANNEL_ID,
+                    "guild_id": guild_id,
+                    "author": {
+                        "id": MASTER_ID,
+                        "username": "master",
+                        "discriminator": "0001",
+                        "public_flags": 0,
+                    },
+                    "attachments": [],
+                    "components": [],
+                    "embeds": [],
+                    "mentions": [],
+                    "mention_roles": [],
+                    "mention_everyone": false,
+                    "pinned": false,
+                    "tts": false,
+                    "flags": 0,
+                    "timestamp": "2022-03-01T12:00:00.000000+00:00",
+                    "edited_timestamp": null,
+                },
+            }),
+        )
+        .await;
+        id
+    }
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
+
+impl RunningBot {
+    /// Shuts the bot down and fails the test if it stopped with an error
+    pub async fn stop(self) {
+        self.shutdown.shutdown();
+        tokio::time::timeout(TIMEOUT, self.task)
+            .await
+            .expect("Bot did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+}
+
+/// Whether the request was made with the method to the path
+pub fn is_request(request: &Value, method: &str, path: &str) -> bool {
+    request["method"] == method && request["path"] == path
+}
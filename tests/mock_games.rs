@@ -0,0 +1,197 @@
+//! The farming commands and every mini-game of the simulated Dank Memer, played by the
+//! built-in commands
+
+mod common;
+
+use common::{is_request, Mock, RunningBot, CHANNEL_ID};
+use serde_json::{json, Value};
+
+/// Starts the mini-game for the bot and returns its result once the bot clicked
+async fn play(name: &str) -> Value {
+    let mock = Mock::start();
+    let bot = mock.connect_bot().await;
+
+    let started = mock
+        .post(
+            &format!("/mock/games/{}", name),
+            json!({ "channel_id": CHANNEL_ID }),
+        )
+        .await;
+    let message_id = started["message_id"].clone();
+    let result = finish(&mock, bot, |result| result["message_id"] == message_id).await;
+    assert_eq!(result["game"], name);
+    result
+}
+
+/// Lets the bot send the command and returns the result of the game Pepe answers with,
+/// along with the interactions the bot made on it
+async fn farm(command: &str, game: &str) -> (Value, Vec<Value>) {
+    let mut mock = Mock::start();
+    let bot = mock.connect_bot().await;
+
+    mock.command(&format!("say {}", command)).await;
+    let result = finish(&mock, bot, |result| result["game"] == game).await;
+    let interactions = mock
+        .get("/mock/requests")
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|request| {
+            is_request(request, "POST", "/interactions")
+                && request["body"]["message_id"] == result["message_id"]
+        })
+        .map(|request| request["body"]["data"].clone())
+        .collect();
+    (result, interactions)
+}
+
+/// Waits for the result that passes the check, stops the bot and returns the result
+async fn finish(mock: &Mock, bot: RunningBot, check: impl Fn(&Value) -> bool) -> Value {
+    let results = mock
+        .wait_for("/mock/results", |results| {
+            results.as_array().unwrap().iter().any(&check)
+        })
+        .await;
+
+    bot.stop().await;
+    results
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|result| check(result))
+        .cloned()
+        .unwrap()
+}
+
+/// Custom ids of the clicked buttons and select menus
+fn custom_ids(interactions: &[Value]) -> Vec<&str> {
+    interactions
+        .iter()
+        .map(|data| data["custom_id"].as_str().unwrap())
+        .collect()
+}
+
+async fn assert_won(name: &str) {
+    let result = play(name).await;
+    assert_eq!(result["correct"], true, "{}", result);
+}
+
+#[tokio::test]
+async fn word_order() {
+    assert_won("word-order").await;
+}
+
+#[tokio::test]
+async fn emoji_match() {
+    assert_won("emoji-match").await;
+}
+
+#[tokio::test]
+async fn soccer() {
+    assert_won("soccer").await;
+}
+
+#[tokio::test]
+async fn color_match() {
+    assert_won("color-match").await;
+}
+
+#[tokio::test]
+async fn dunk() {
+    assert_won("dunk").await;
+}
+
+#[tokio::test]
+async fn boss() {
+    assert_won("boss").await;
+}
+
+/// The bot can only guess, so it only has to answer
+#[tokio::test]
+async fn trivia_night() {
+    let result = play("trivia-night").await;
+    assert!(result["clicked"].as_str().unwrap().starts_with("Answer"));
+}
+
+/// Pepe picks a higher secret number for hints up to 50, a lower one above
+#[tokio::test]
+async fn high_low_guesses_from_the_hint() {
+    let (result, interactions) = farm("pls hl", "hl").await;
+    assert_eq!(result["correct"], true, "{}", result);
+    assert_eq!(result["clicked"], result["expected"]);
+    let expected = if result["expected"] == "Higher" {
+        "hl-higher"
+    } else {
+        "hl-lower"
+    };
+    assert_eq!(custom_ids(&interactions), [expected]);
+}
+
+#[tokio::test]
+async fn hunt_dodges_the_fireball() {
+    let (result, interactions) = farm("pls hunt", "hunt").await;
+    assert_eq!(result["correct"], true, "{}", result);
+    let fireball = result["expected"]
+        .as_str()
+        .unwrap()
+        .trim_start_matches("not ");
+    assert_ne!(result["clicked"], fireball);
+    assert_eq!(interactions.len(), 1);
+}
+
+#[tokio::test]
+async fn fish_is_caught_where_it_swims() {
+    let (result, interactions) = farm("pls fish", "fish").await;
+    assert_eq!(result["correct"], true, "{}", result);
+    assert_eq!(result["clicked"], result["expected"]);
+    let expected = format!(
+        "fish-{}",
+        result["expected"].as_str().unwrap().to_lowercase()
+    );
+    assert_eq!(custom_ids(&interactions), [expected.as_str()]);
+}
+
+/// The bot can only guess, so it only has to answer once
+#[tokio::test]
+async fn trivia_picks_one_answer() {
+    let (result, interactions) = farm("pls trivia", "trivia").await;
+    assert!(!result["clicked"].as_str().unwrap().is_empty());
+    assert_eq!(interactions.len(), 1);
+    assert!(custom_ids(&interactions)[0].starts_with("trivia-"));
+}
+
+#[tokio::test]
+async fn post_memes_picks_a_meme_type() {
+    let (result, interactions) = farm("pls pm", "pm").await;
+    assert_eq!(result["correct"], true, "{}", result);
+    assert_eq!(interactions.len(), 1);
+    assert!(custom_ids(&interactions)[0].starts_with("pm-"));
+}
+
+/// Go Live, pick a game, Go Live again, one of the stream actions and End Interaction
+#[tokio::test]
+async fn stream_goes_live_with_a_game() {
+    let (result, interactions) = farm("pls stream", "stream").await;
+    assert_eq!(result["correct"], true, "{}", result);
+
+    let ids = custom_ids(&interactions);
+    assert_eq!(ids.len(), 5, "{:?}", ids);
+    assert_eq!(ids[..3], ["stream-live", "stream-game", "stream-start"]);
+    assert!(["stream-ad", "stream-chat", "stream-donations"].contains(&ids[3]));
+    assert_eq!(ids[4], "stream-end");
+    // The select menu sends the value of the chosen option
+    let game = interactions[1]["values"][0].as_str().unwrap();
+    assert!(["fortnite", "minecraft", "among us", "rocket league"].contains(&game));
+    assert_eq!(interactions[1]["component_type"], 3);
+}
+
+#[tokio::test]
+async fn pet_is_cared_for_before_the_end() {
+    let (result, interactions) = farm("pls pet", "pet").await;
+    assert_eq!(result["correct"], true, "{}", result);
+    assert_eq!(
+        custom_ids(&interactions),
+        ["pet-feed", "pet-pat", "pet-wash", "pet-end"]
+    );
+}
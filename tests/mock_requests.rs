@@ -0,0 +1,189 @@
+//! The REST requests the built-in master commands make, as seen by the mock
+
+mod common;
+
+use std::time::Duration;
+
+use common::{is_request, Mock, CHANNEL_ID, MASTER_ID};
+use pepe_bot::Bot;
+use serde_json::json;
+
+#[tokio::test]
+async fn shutdown_before_connect_is_kept() {
+    let mock = Mock::start();
+    let bot = Bot::builder("token")
+        .gateway_url(&mock.gateway_url)
+        .api_url(&mock.api_url)
+        .build();
+    bot.shutdown_handle().shutdown();
+
+    tokio::time::timeout(Duration::from_secs(5), bot.connect())
+        .await
+        .expect("Bot did not stop")
+        .unwrap();
+    assert_eq!(mock.get("/mock/sessions").await["sessions"], 0);
+}
+
+#[tokio::test]
+async fn ping_replies_to_the_master() {
+    let mut mock = Mock::start();
+    let bot = mock.connect_bot().await;
+
+    let command_id = mock.command("ping").await;
+    let reply = mock
+        .wait_for_request(|request| {
+            is_request(
+                request,
+                "POST",
+                &format!("/channels/{}/messages", CHANNEL_ID),
+            )
+        })
+        .await;
+    assert!(reply["body"]["content"]
+        .as_str()
+        .unwrap()
+        .starts_with("Pong!"));
+    assert_eq!(reply["body"]["message_reference"]["message_id"], command_id);
+
+    bot.stop().await;
+}
+
+#[tokio::test]
+async fn ping_replies_in_direct_messages() {
+    let mut mock = Mock::start();
+    let bot = mock.connect_bot().await;
+
+    let command_id = mock.direct_command("ping").await;
+    let reply = mock
+        .wait_for_request(|request| {
+            is_request(
+                request,
+                "POST",
+                &format!("/channels/{}/messages", CHANNEL_ID),
+            )
+        })
+        .await;
+    assert_eq!(
+        reply["body"]["message_reference"],
+        json!({"channel_id": CHANNEL_ID, "message_id": command_id})
+    );
+
+    bot.stop().await;
+}
+
+#[tokio::test]
+async fn say_sends_the_message() {
+    let mut mock = Mock::start();
+    let bot = mock.connect_bot().await;
+
+    mock.command("say pls beg").await;
+    let message = mock
+        .wait_for_request(|request| {
+            is_request(
+                request,
+                "POST",
+                &format!("/channels/{}/messages", CHANNEL_ID),
+            )
+        })
+        .await;
+    assert_eq!(
+        message["body"],
+        json!({"content": "pls beg", "message_reference": null})
+    );
+
+    bot.stop().await;
+}
+
+#[tokio::test]
+async fn clean_waits_for_the_confirmation() {
+    let mut mock = Mock::start();
+    let bot = mock.connect_bot().await;
+
+    mock.command("say hello").await;
+    mock.command("clean").await;
+    let confirm = mock
+        .wait_for_request(|request| {
+            request["method"] == "PUT" && request["path"].as_str().unwrap().ends_with("/@me")
+        })
+        .await;
+    // /channels/{channel}/messages/{message}/reactions/{emoji}/@me
+    let confirmation_id = confirm["path"].as_str().unwrap().split('/').nth(4).unwrap();
+    let requests = mock.get("/mock/requests").await;
+    assert!(!requests
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|request| request["method"] == "DELETE"));
+
+    mock.post(
+        "/mock/dispatch",
+        json!({
+            "t": "MESSAGE_REACTION_ADD",
+            "d": {
+                "user_id": MASTER_ID,
+                "channel_id": CHANNEL_ID,
+                "message_id": confirmation_id,
+                "emoji": {"id": null, "name": "\u{2705}"},
+            },
+        }),
+    )
+    .await;
+    // The confirmation and the said message are both messages of the bot
+    let requests = mock
+        .wait_for("/mock/requests", |requests| {
+            requests
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|request| request["method"] == "DELETE")
+                .count()
+                >= 2
+        })
+        .await;
+    assert!(requests
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|request| is_request(
+            request,
+            "DELETE",
+            &format!("/channels/{}/messages/{}", CHANNEL_ID, confirmation_id)
+        )));
+
+    bot.stop().await;
+}
+
+#[tokio::test]
+async fn refused_account_notifies_the_master() {
+    let mut mock = Mock::start();
+    let bot = mock.connect_bot().await;
+
+    mock.post(
+        "/mock/fail",
+        json!({
+            "path": format!("/channels/{}/messages", CHANNEL_ID),
+            "status": 401,
+            "code": 40001,
+        }),
+    )
+    .await;
+    mock.command("say pls beg").await;
+
+    let dm_channel = mock
+        .wait_for_request(|request| is_request(request, "POST", "/users/@me/channels"))
+        .await;
+    assert_eq!(dm_channel["body"], json!({ "recipient_id": MASTER_ID }));
+    let notification = mock
+        .wait_for_request(|request| {
+            request["method"] == "POST"
+                && request["path"].as_str().unwrap().ends_with("/messages")
+                && request["path"] != format!("/channels/{}/messages", CHANNEL_ID)
+        })
+        .await;
+    assert!(notification["body"]["content"]
+        .as_str()
+        .unwrap()
+        .starts_with("Stopped farming"));
+
+    bot.stop().await;
+}